@@ -1,44 +1,41 @@
 // criterion is benchmarkinf framwork for Rust 
 // prevent the compiler from optimizing away computations in a benchmark.
-use criterion::{criterion_group, criterion_main, Criterion, black_box,BenchmarkGroup,Throughput};
-use benchmark::{sample_data, SampleData}; // import sample data
-use bincode;
-use bcs;
-use serde_json;
-use borsh;
-use rmp_serde;
-use prost::Message;
+use criterion::{criterion_group, criterion_main, Criterion, black_box,BatchSize,BenchmarkGroup,BenchmarkId,Throughput};
+use benchmark::{codecs, sample_data, SampleData}; // import sample data and codec registry
+use benchmark::evolution::{sample_data_v2, SampleDataV2};
+use benchmark::{payloads, Codec, Protobuf};
+use merkle_tree::{Blake2bHasher, Hasher, MerkleTree, Sha256Hasher, Sha512Hasher};
+use ed25519_dalek::{Signer, SigningKey, Verifier};
+use secp256k1::{Message, Secp256k1, SecretKey};
+use sha2::{Digest, Sha256};
+use benchmark::alloc_profile::{self, AllocStats};
+
+#[cfg(feature = "alloc-profile")]
+#[global_allocator]
+static GLOBAL: alloc_profile::CountingAllocator = alloc_profile::CountingAllocator;
+
+// printed right before criterion's timing of the same benchmark
+fn report_allocations(name: &str, payload: &str, alloc: AllocStats) {
+    if alloc_profile::enabled() {
+        println!("{name}/{payload}: {} allocations, {} peak bytes", alloc.allocations, alloc.peak_bytes);
+    }
+}
 
 fn benchmark_serialization(c: &mut Criterion) {  // for serialization
     let mut group: BenchmarkGroup<_> = c.benchmark_group("Serialization");
-    let data = sample_data(); //data 
-
-
-    group.throughput(Throughput::Bytes(std::mem::size_of_val(&data) as u64));
-
-    group.bench_function("bincode serialize", |b| { // register a benchmark
-        b.iter(|| bincode::serialize(black_box(&data)).unwrap()) // repeats the test multiple time
-    });
-
-    group.bench_function("bcs serialize", |b| {
-        b.iter(|| bcs::to_bytes(black_box(&data)).unwrap())
-    });
-
-    group.bench_function("serde_json serialize", |b| {
-        b.iter(|| serde_json::to_vec(black_box(&data)).unwrap())
-    });
 
-    group.bench_function("borsh serialize", |b| {
-        b.iter(|| borsh::to_vec(black_box(&data)).unwrap())
-    });
+    for (payload, data) in payloads() {
+        for codec in codecs::<SampleData>() {
+            let name = format!("{} serialize", codec.name());
+            let (encoded, alloc) = alloc_profile::measure(|| codec.encode(&data).unwrap());
+            report_allocations(&name, payload, alloc);
 
-    group.bench_function("rmp serialize", |b| {
-        b.iter(|| rmp_serde::to_vec(black_box(&data)).unwrap())
-    });
-
-    group.bench_function("protobuf serialize", |b| {
-        b.iter(|| data.encode_to_vec()) // Protobuf serialization
-    });
+            group.throughput(Throughput::Bytes(encoded.len() as u64));
+            group.bench_function(BenchmarkId::new(name, payload), |b| { // register a benchmark
+                b.iter(|| codec.encode(black_box(&data)).unwrap()) // repeats the test multiple time
+            });
+        }
+    }
 
     group.finish();
 }
@@ -46,58 +43,135 @@ fn benchmark_serialization(c: &mut Criterion) {  // for serialization
 
 fn benchmark_deserialization(c: &mut Criterion) {  // for deserialization
     let mut group: BenchmarkGroup<_> = c.benchmark_group("Deserialization");
-    let data = sample_data();
-
-    let bincode_data = bincode::serialize(&data).unwrap();
-    let bcs_data = bcs::to_bytes(&data).unwrap();
-    let json_data = serde_json::to_vec(&data).unwrap();
-    let borsh_data = borsh::to_vec(&data).unwrap();
-    let rmp_data = rmp_serde::to_vec(&data).unwrap();
-    let protobuf_data = data.encode_to_vec();
 
-    group.bench_function("bincode deserialize", |b| {
-        b.iter(|| bincode::deserialize::<SampleData>(black_box(&bincode_data)).unwrap())
-    });
+    for (payload, data) in payloads() {
+        for codec in codecs::<SampleData>() {
+            let name = format!("{} deserialize", codec.name());
+            let encoded = codec.encode(&data).unwrap();
+            let (_, alloc) = alloc_profile::measure(|| codec.decode(&encoded).unwrap());
+            report_allocations(&name, payload, alloc);
+
+            group.throughput(Throughput::Bytes(encoded.len() as u64));
+            group.bench_function(BenchmarkId::new(name, payload), |b| {
+                b.iter(|| codec.decode(black_box(&encoded)).unwrap())
+            });
+        }
+    }
+    group.finish();
+}
 
-    group.bench_function("bcs deserialize", |b| {
-        b.iter(|| bcs::from_bytes::<SampleData>(black_box(&bcs_data)).unwrap())
-    });
+fn benchmark_size(c: &mut Criterion) { // check size of data
+    let data = sample_data();
 
-    group.bench_function("serde_json deserialize", |b| {
-        b.iter(|| serde_json::from_slice::<SampleData>(black_box(&json_data)).unwrap())
-    });
+    for codec in codecs::<SampleData>() {
+        let size = codec.encode(&data).unwrap().len();
+        c.bench_function(&format!("size {}", codec.name()), |b| b.iter(|| black_box(size)));
+    }
+}
 
-    group.bench_function("borsh deserialize", |b| {
-        b.iter(|| borsh::from_slice::<SampleData>(black_box(&borsh_data)).unwrap())
-    });
+fn benchmark_schema_evolution(c: &mut Criterion) { // decode messages written with the other schema version
+    let mut group: BenchmarkGroup<_> = c.benchmark_group("Schema evolution");
+    let v1 = sample_data();
+    let v2 = sample_data_v2();
+
+    for (old, new) in codecs::<SampleData>().iter().zip(codecs::<SampleDataV2>().iter()) {
+        // formats that can't read the other version are skipped (see evolution::run for the full table)
+        if let Ok(bytes) = old.encode(&v1) && new.decode(&bytes).is_ok() {
+            group.bench_function(format!("{} v1->v2", new.name()), |b| {
+                b.iter(|| new.decode(black_box(&bytes)).unwrap())
+            });
+        }
+        if let Ok(bytes) = new.encode(&v2) && old.decode(&bytes).is_ok() {
+            group.bench_function(format!("{} v2->v1", old.name()), |b| {
+                b.iter(|| old.decode(black_box(&bytes)).unwrap())
+            });
+        }
+    }
+    group.finish();
+}
 
-    group.bench_function("rmp deserialize", |b| {
-        b.iter(|| rmp_serde::from_slice::<SampleData>(black_box(&rmp_data)).unwrap())
-    });
+// leaves are protobuf encoded messages, like the ones we put on the wire
+fn merkle_leaves(count: u32) -> Vec<Vec<u8>> {
+    (0..count)
+        .map(|id| Protobuf.encode(&SampleData { id, ..sample_data() }).unwrap())
+        .collect()
+}
 
-    group.bench_function("protobuf deserialize", |b| {
-        b.iter(|| SampleData::decode(black_box(&*protobuf_data)).unwrap())
-    });
+fn bench_hasher<H: Hasher>(c: &mut Criterion, hasher: &str) {
+    let mut group: BenchmarkGroup<_> = c.benchmark_group(format!("Merkle {hasher}"));
+
+    // per leaf hashing of each encoded payload
+    for (payload, data) in payloads() {
+        let leaf = Protobuf.encode(&data).unwrap();
+        group.throughput(Throughput::Bytes(leaf.len() as u64));
+        group.bench_function(format!("hash {payload} leaf"), |b| {
+            b.iter(|| H::hash(black_box(&leaf)))
+        });
+    }
+
+    // full tree build, cloning the leaves outside the timed part
+    for size in [16, 256, 4096] {
+        let leaves = merkle_leaves(size);
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_function(format!("build tree {size} leaves"), |b| {
+            b.iter_batched(|| leaves.clone(), |leaves| MerkleTree::<H>::new(black_box(leaves)), BatchSize::SmallInput)
+        });
+    }
     group.finish();
 }
 
-fn benchmark_size(c: &mut Criterion) { // check size of data
-    let data = sample_data();
+fn benchmark_merkle(c: &mut Criterion) {
+    bench_hasher::<Sha256Hasher>(c, "sha256");
+    bench_hasher::<Sha512Hasher>(c, "sha512");
+    bench_hasher::<Blake2bHasher>(c, "blake2b");
+}
+
+fn benchmark_secp256k1(c: &mut Criterion) { // ecdsa over sha256 of the encoded message
+    let mut group: BenchmarkGroup<_> = c.benchmark_group("secp256k1");
+    let secp = Secp256k1::new();
+    let secret_key = SecretKey::from_slice(&[0x42; 32]).unwrap();
+    let public_key = secret_key.public_key(&secp);
+
+    for (payload, data) in payloads() {
+        for codec in codecs::<SampleData>() {
+            let bytes = codec.encode(&data).unwrap();
+            let digest = |bytes: &[u8]| Message::from_digest(Sha256::digest(bytes).into());
+            let signature = secp.sign_ecdsa(&digest(&bytes), &secret_key);
+
+            group.throughput(Throughput::Bytes(bytes.len() as u64));
+            group.bench_function(format!("sign {} {payload}", codec.name()), |b| {
+                b.iter(|| secp.sign_ecdsa(&digest(black_box(&bytes)), &secret_key))
+            });
+            group.bench_function(format!("verify {} {payload}", codec.name()), |b| {
+                b.iter(|| secp.verify_ecdsa(&digest(black_box(&bytes)), &signature, &public_key).unwrap())
+            });
+        }
+    }
+    group.finish();
+}
 
-    let bincode_size = bincode::serialize(&data).unwrap().len();
-    let bcs_size = bcs::to_bytes(&data).unwrap().len();
-    let json_size = serde_json::to_vec(&data).unwrap().len();
-    let borsh_size = borsh::to_vec(&data).unwrap().len();
-    let rmp_size = rmp_serde::to_vec(&data).unwrap().len();
-    let protobuf_size = data.encode_to_vec().len();
-
-    c.bench_function("size bincode", |b| b.iter(|| black_box(bincode_size)));
-    c.bench_function("size bcs", |b| b.iter(|| black_box(bcs_size)));
-    c.bench_function("size json", |b| b.iter(|| black_box(json_size)));
-    c.bench_function("size borsh", |b| b.iter(|| black_box(borsh_size)));
-    c.bench_function("size rmp", |b| b.iter(|| black_box(rmp_size)));
-    c.bench_function("size protobuf", |b| b.iter(|| black_box(protobuf_size)));
+fn benchmark_ed25519(c: &mut Criterion) {
+    let mut group: BenchmarkGroup<_> = c.benchmark_group("ed25519");
+    let signing_key = SigningKey::from_bytes(&[0x42; 32]);
+    let verifying_key = signing_key.verifying_key();
+
+    for (payload, data) in payloads() {
+        for codec in codecs::<SampleData>() {
+            let bytes = codec.encode(&data).unwrap();
+            let signature = signing_key.sign(&bytes);
+
+            group.throughput(Throughput::Bytes(bytes.len() as u64));
+            group.bench_function(format!("sign {} {payload}", codec.name()), |b| {
+                b.iter(|| signing_key.sign(black_box(&bytes)))
+            });
+            group.bench_function(format!("verify {} {payload}", codec.name()), |b| {
+                b.iter(|| verifying_key.verify(black_box(&bytes), &signature).unwrap())
+            });
+        }
+    }
+    group.finish();
 }
 
-criterion_group!(benches, benchmark_serialization, benchmark_deserialization, benchmark_size); // group all bechmarks
-criterion_main!(benches);
+criterion_group!(benches, benchmark_serialization, benchmark_deserialization, benchmark_size, benchmark_schema_evolution); // group all bechmarks
+criterion_group!(crypto, benchmark_merkle, benchmark_secp256k1, benchmark_ed25519); // hashing and signing cost per message
+criterion_main!(benches, crypto);
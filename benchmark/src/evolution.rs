@@ -0,0 +1,130 @@
+use crate::{codecs, report::time_ns, Codec, SampleData};
+use borsh::{BorshDeserialize, BorshSerialize};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use std::hint::black_box;
+
+// next version of SampleData with one added optional field
+// `SampleData` itself plays the role of v1
+#[derive(Clone, PartialEq, Serialize, Deserialize, BorshSerialize, BorshDeserialize, Message)]
+pub struct SampleDataV2 {
+    #[prost(uint32, tag = "1")]
+    pub id: u32,
+
+    #[prost(string, tag = "2")]
+    pub name: String,
+
+    #[prost(bool, tag = "3")]
+    pub active: bool,
+
+    #[prost(bytes, tag = "4")]
+    pub values: Vec<u8>,
+
+    #[prost(string, optional, tag = "5")]
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+impl From<&SampleData> for SampleDataV2 {
+    // what a v2 reader should see for a v1 message
+    fn from(v1: &SampleData) -> Self {
+        SampleDataV2 {
+            id: v1.id,
+            name: v1.name.clone(),
+            active: v1.active,
+            values: v1.values.clone(),
+            description: None,
+        }
+    }
+}
+
+impl From<&SampleDataV2> for SampleData {
+    // what a v1 reader should see for a v2 message (new field dropped)
+    fn from(v2: &SampleDataV2) -> Self {
+        SampleData {
+            id: v2.id,
+            name: v2.name.clone(),
+            active: v2.active,
+            values: v2.values.clone(),
+        }
+    }
+}
+
+pub fn sample_data_v2() -> SampleDataV2 {
+    SampleDataV2 {
+        description: Some("added in v2".to_string()),
+        ..SampleDataV2::from(&crate::sample_data())
+    }
+}
+
+// what happened when decoding a message written with the other schema version
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Compatibility {
+    Compatible, // decoded to the expected value
+    Failed,     // decoder returned an error
+    Corrupted,  // decoded without error but the value is wrong
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EvolutionResult {
+    pub codec: String,
+    pub direction: String, // "v1->v2" or "v2->v1"
+    pub compatibility: Compatibility,
+    pub decode_ns: Option<f64>, // only measured when decoding succeeds
+}
+
+// encode with `writer`, decode with `reader` and compare to `expected`
+pub fn check<W, R: PartialEq>(
+    writer: &dyn Codec<W>,
+    reader: &dyn Codec<R>,
+    value: &W,
+    expected: &R,
+) -> Compatibility {
+    let Ok(bytes) = writer.encode(value) else {
+        return Compatibility::Failed;
+    };
+    match reader.decode(&bytes) {
+        Ok(decoded) if &decoded == expected => Compatibility::Compatible,
+        Ok(_) => Compatibility::Corrupted,
+        Err(_) => Compatibility::Failed,
+    }
+}
+
+fn measure<W, R: PartialEq>(
+    writer: &dyn Codec<W>,
+    reader: &dyn Codec<R>,
+    direction: &str,
+    value: &W,
+    expected: &R,
+    iterations: u32,
+) -> EvolutionResult {
+    let compatibility = check(writer, reader, value, expected);
+    let decode_ns = match (compatibility, writer.encode(value)) {
+        (Compatibility::Failed, _) | (_, Err(_)) => None,
+        (_, Ok(bytes)) => Some(time_ns(iterations, || reader.decode(black_box(&bytes)))),
+    };
+
+    EvolutionResult {
+        codec: reader.name().to_string(),
+        direction: direction.to_string(),
+        compatibility,
+        decode_ns,
+    }
+}
+
+// old writer -> new reader and new writer -> old reader for every codec
+pub fn run(iterations: u32) -> Vec<EvolutionResult> {
+    let v1 = crate::sample_data();
+    let v2 = sample_data_v2();
+    let v1_as_v2 = SampleDataV2::from(&v1);
+    let v2_as_v1 = SampleData::from(&v2);
+
+    let mut results = Vec::new();
+    // both registries list the same formats in the same order
+    for (old, new) in codecs::<SampleData>().iter().zip(codecs::<SampleDataV2>().iter()) {
+        results.push(measure(old.as_ref(), new.as_ref(), "v1->v2", &v1, &v1_as_v2, iterations));
+        results.push(measure(new.as_ref(), old.as_ref(), "v2->v1", &v2, &v2_as_v1, iterations));
+    }
+    results
+}
@@ -1,8 +1,13 @@
-use serde::{Serialize, Deserialize};
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
 use borsh::{BorshSerialize, BorshDeserialize};
 use prost::Message;
+use std::error::Error;
 
-#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Message )]
+pub mod alloc_profile;
+pub mod evolution;
+pub mod report;
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, BorshSerialize, BorshDeserialize, Message )]
 pub struct SampleData {
     #[prost(uint32, tag = "1")]
     pub id: u32,
@@ -40,3 +45,135 @@ pub fn sample_data() -> SampleData {
         values: vec![1]
     }
 }
+
+// bigger message closer to what we send on the wire
+pub fn large_sample_data() -> SampleData {
+    SampleData {
+        id: 1231566666,
+        name: "Hi My Name is Rohit i am from India and i am learning Rust ".repeat(16),
+        active: true,
+        values: (0..4096).map(|i| (i % 251) as u8).collect(),
+    }
+}
+
+// named payloads used by the reports
+pub fn payloads() -> Vec<(&'static str, SampleData)> {
+    vec![("small", sample_data()), ("large", large_sample_data())]
+}
+
+// error returned by any codec (each format has its own error type)
+pub type CodecError = Box<dyn Error + Send + Sync>;
+
+// common interface for every serialization format we benchmark
+// adding a new format only needs one impl and an entry in `codecs()`
+pub trait Codec<T> {
+    fn name(&self) -> &'static str;
+    fn encode(&self, value: &T) -> Result<Vec<u8>, CodecError>;
+    fn decode(&self, bytes: &[u8]) -> Result<T, CodecError>;
+}
+
+pub struct Bincode;
+impl<T: Serialize + DeserializeOwned> Codec<T> for Bincode {
+    fn name(&self) -> &'static str {
+        "bincode"
+    }
+
+    fn encode(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+        Ok(bincode::serialize(value)?)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<T, CodecError> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+pub struct Bcs;
+impl<T: Serialize + DeserializeOwned> Codec<T> for Bcs {
+    fn name(&self) -> &'static str {
+        "bcs"
+    }
+
+    fn encode(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+        Ok(bcs::to_bytes(value)?)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<T, CodecError> {
+        Ok(bcs::from_bytes(bytes)?)
+    }
+}
+
+pub struct Json;
+impl<T: Serialize + DeserializeOwned> Codec<T> for Json {
+    fn name(&self) -> &'static str {
+        "serde_json"
+    }
+
+    fn encode(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<T, CodecError> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+pub struct Borsh;
+impl<T: BorshSerialize + BorshDeserialize> Codec<T> for Borsh {
+    fn name(&self) -> &'static str {
+        "borsh"
+    }
+
+    fn encode(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+        Ok(borsh::to_vec(value)?)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<T, CodecError> {
+        Ok(borsh::from_slice(bytes)?)
+    }
+}
+
+pub struct Rmp;
+impl<T: Serialize + DeserializeOwned> Codec<T> for Rmp {
+    fn name(&self) -> &'static str {
+        "rmp"
+    }
+
+    fn encode(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+        Ok(rmp_serde::to_vec(value)?)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<T, CodecError> {
+        Ok(rmp_serde::from_slice(bytes)?)
+    }
+}
+
+// protobuf encoding through prost
+pub struct Protobuf;
+impl<T: Message + Default> Codec<T> for Protobuf {
+    fn name(&self) -> &'static str {
+        "protobuf"
+    }
+
+    fn encode(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+        Ok(value.encode_to_vec())
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<T, CodecError> {
+        Ok(T::decode(bytes)?)
+    }
+}
+
+// registry of all supported formats, benches and reports iterate over this
+pub fn codecs<T>() -> Vec<Box<dyn Codec<T>>>
+where
+    T: Serialize + DeserializeOwned + BorshSerialize + BorshDeserialize + Message + Default,
+{
+    vec![
+        Box::new(Bincode),
+        Box::new(Bcs),
+        Box::new(Json),
+        Box::new(Borsh),
+        Box::new(Rmp),
+        Box::new(Protobuf),
+    ]
+}
@@ -0,0 +1,81 @@
+use benchmark::report::{run_suite, Report};
+use std::{error::Error, fs, process};
+
+#[cfg(feature = "alloc-profile")]
+#[global_allocator]
+static GLOBAL: benchmark::alloc_profile::CountingAllocator = benchmark::alloc_profile::CountingAllocator;
+
+const USAGE: &str = "Usage: benchmark [--iterations <n>] [--json <path>] [--markdown <path>] [--baseline <path>] [--threshold <percent>]";
+
+struct Args {
+    iterations: u32,
+    json: Option<String>,
+    markdown: Option<String>,
+    baseline: Option<String>,
+    threshold: f64,
+}
+
+fn parse_args() -> Result<Args, Box<dyn Error>> {
+    let mut args = Args {
+        iterations: 10_000,
+        json: None,
+        markdown: None,
+        baseline: None,
+        threshold: 10.0,
+    };
+
+    let mut input = std::env::args().skip(1);
+    while let Some(flag) = input.next() {
+        let mut value = || input.next().ok_or_else(|| format!("missing value for {flag}"));
+        match flag.as_str() {
+            "--iterations" => args.iterations = value()?.parse()?,
+            "--json" => args.json = Some(value()?),
+            "--markdown" => args.markdown = Some(value()?),
+            "--baseline" => args.baseline = Some(value()?),
+            "--threshold" => args.threshold = value()?.parse()?,
+            "--help" | "-h" => {
+                println!("{USAGE}");
+                process::exit(0);
+            }
+            other => return Err(format!("unknown argument: {other}\n{USAGE}").into()),
+        }
+    }
+
+    Ok(args)
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = parse_args()?;
+
+    let report = run_suite(args.iterations).map_err(|e| e.to_string())?;
+    print!("{}", report.to_markdown());
+
+    if let Some(path) = &args.json {
+        fs::write(path, report.to_json()?)?;
+        println!("JSON summary written to {path}");
+    }
+    if let Some(path) = &args.markdown {
+        fs::write(path, report.to_markdown())?;
+        println!("Markdown summary written to {path}");
+    }
+
+    if let Some(path) = &args.baseline {
+        let baseline = Report::from_json(&fs::read_to_string(path)?)?;
+        let regressions = report.compare(&baseline, args.threshold);
+
+        if regressions.is_empty() {
+            println!("No regressions above {}% against {path}", args.threshold);
+        } else {
+            eprintln!("Regressions above {}% against {path}:", args.threshold);
+            for r in &regressions {
+                eprintln!(
+                    "{} {} {}: {:.1} ns -> {:.1} ns (+{:.1}%)",
+                    r.codec, r.payload, r.op, r.baseline_ns, r.current_ns, r.change_percent
+                );
+            }
+            process::exit(1);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,161 @@
+use crate::{alloc_profile, codecs, evolution::{self, EvolutionResult}, payloads, CodecError, SampleData};
+use serde::{Deserialize, Serialize};
+use std::{fmt::Write, hint::black_box, time::Instant};
+
+// one measurement of a codec on a payload
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchResult {
+    pub codec: String,
+    pub payload: String,
+    pub op: String, // "encode" or "decode"
+    pub ns_per_op: f64,
+    pub bytes: usize, // encoded size
+    pub mb_per_sec: f64,
+    // filled in when built with the alloc-profile feature
+    #[serde(default)]
+    pub allocations: Option<usize>,
+    #[serde(default)]
+    pub peak_bytes: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Report {
+    pub iterations: u32,
+    pub results: Vec<BenchResult>,
+    #[serde(default)] // older baselines don't have this
+    pub evolution: Vec<EvolutionResult>,
+}
+
+// a result that got slower than the baseline allows
+#[derive(Debug, Clone)]
+pub struct Regression {
+    pub codec: String,
+    pub payload: String,
+    pub op: String,
+    pub baseline_ns: f64,
+    pub current_ns: f64,
+    pub change_percent: f64,
+}
+
+// average time of one call to `f` in nanoseconds
+pub(crate) fn time_ns<R>(iterations: u32, mut f: impl FnMut() -> R) -> f64 {
+    // warm up caches and allocator first
+    for _ in 0..(iterations / 10).max(1) {
+        black_box(f());
+    }
+    let start = Instant::now();
+    for _ in 0..iterations {
+        black_box(f());
+    }
+    start.elapsed().as_nanos() as f64 / iterations as f64
+}
+
+fn mb_per_sec(bytes: usize, ns_per_op: f64) -> f64 {
+    if ns_per_op == 0.0 {
+        return 0.0;
+    }
+    // bytes per ns == GB/s, scale to MB/s
+    bytes as f64 / ns_per_op * 1000.0
+}
+
+// run every codec on every payload, encode and decode
+pub fn run_suite(iterations: u32) -> Result<Report, CodecError> {
+    let mut report = Report { iterations, ..Default::default() };
+
+    for (payload, data) in payloads() {
+        for codec in codecs::<SampleData>() {
+            let encoded = codec.encode(&data)?;
+            let bytes = encoded.len();
+
+            let encode_ns = time_ns(iterations, || codec.encode(black_box(&data)));
+            let decode_ns = time_ns(iterations, || codec.decode(black_box(&encoded)));
+
+            let (_, encode_alloc) = alloc_profile::measure(|| codec.encode(black_box(&data)));
+            let (_, decode_alloc) = alloc_profile::measure(|| codec.decode(black_box(&encoded)));
+
+            for (op, ns_per_op, alloc) in [("encode", encode_ns, encode_alloc), ("decode", decode_ns, decode_alloc)] {
+                let alloc = alloc_profile::enabled().then_some(alloc);
+                report.results.push(BenchResult {
+                    codec: codec.name().to_string(),
+                    payload: payload.to_string(),
+                    op: op.to_string(),
+                    ns_per_op,
+                    bytes,
+                    mb_per_sec: mb_per_sec(bytes, ns_per_op),
+                    allocations: alloc.map(|a| a.allocations),
+                    peak_bytes: alloc.map(|a| a.peak_bytes),
+                });
+            }
+        }
+    }
+
+    report.evolution = evolution::run(iterations);
+
+    Ok(report)
+}
+
+impl Report {
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut md = String::new();
+        let _ = writeln!(md, "| codec | payload | op | ns/op | bytes | MB/s | allocs | peak bytes |");
+        let _ = writeln!(md, "|---|---|---|---:|---:|---:|---:|---:|");
+        let or_dash = |v: Option<usize>| v.map(|v| v.to_string()).unwrap_or_else(|| "-".to_string());
+        for r in &self.results {
+            let _ = writeln!(
+                md,
+                "| {} | {} | {} | {:.1} | {} | {:.2} | {} | {} |",
+                r.codec, r.payload, r.op, r.ns_per_op, r.bytes, r.mb_per_sec,
+                or_dash(r.allocations), or_dash(r.peak_bytes)
+            );
+        }
+
+        if !self.evolution.is_empty() {
+            let _ = writeln!(md);
+            let _ = writeln!(md, "| codec | direction | result | decode ns/op |");
+            let _ = writeln!(md, "|---|---|---|---:|");
+            for e in &self.evolution {
+                let decode_ns = e.decode_ns.map(|ns| format!("{ns:.1}")).unwrap_or_else(|| "-".to_string());
+                let _ = writeln!(md, "| {} | {} | {:?} | {} |", e.codec, e.direction, e.compatibility, decode_ns);
+            }
+        }
+        md
+    }
+
+    // compare against a baseline, anything slower than `threshold_percent` is a regression
+    pub fn compare(&self, baseline: &Report, threshold_percent: f64) -> Vec<Regression> {
+        let mut regressions = Vec::new();
+
+        for current in &self.results {
+            let Some(base) = baseline.results.iter().find(|b| {
+                b.codec == current.codec && b.payload == current.payload && b.op == current.op
+            }) else {
+                continue; // new codec or payload, nothing to compare with
+            };
+
+            if base.ns_per_op <= 0.0 {
+                continue;
+            }
+            let change_percent = (current.ns_per_op - base.ns_per_op) / base.ns_per_op * 100.0;
+            if change_percent > threshold_percent {
+                regressions.push(Regression {
+                    codec: current.codec.clone(),
+                    payload: current.payload.clone(),
+                    op: current.op.clone(),
+                    baseline_ns: base.ns_per_op,
+                    current_ns: current.ns_per_op,
+                    change_percent,
+                });
+            }
+        }
+
+        regressions
+    }
+}
@@ -0,0 +1,43 @@
+use benchmark::evolution::{check, run, sample_data_v2, Compatibility, SampleDataV2};
+use benchmark::{codecs, sample_data, SampleData};
+
+// expected (v1->v2, v2->v1) result for every codec
+fn expected(codec: &str) -> (Compatibility, Compatibility) {
+    use Compatibility::*;
+    match codec {
+        "protobuf" => (Compatible, Compatible), // unknown tags skipped, missing optional is None
+        "serde_json" => (Compatible, Compatible), // named fields, #[serde(default)] on the new one
+        "rmp" => (Compatible, Failed), // structs are arrays, extra element is rejected
+        "bincode" => (Failed, Compatible), // runs out of bytes, trailing bytes ignored
+        "bcs" => (Failed, Failed), // runs out of bytes, trailing bytes rejected
+        "borsh" => (Failed, Failed), // same as bcs
+        other => panic!("no expectation for codec {other}"),
+    }
+}
+
+#[test]
+fn compatibility_matches_expected() {
+    let v1 = sample_data();
+    let v2 = sample_data_v2();
+
+    for (old, new) in codecs::<SampleData>().iter().zip(codecs::<SampleDataV2>().iter()) {
+        let forward = check(old.as_ref(), new.as_ref(), &v1, &SampleDataV2::from(&v1));
+        let backward = check(new.as_ref(), old.as_ref(), &v2, &SampleData::from(&v2));
+        assert_eq!((forward, backward), expected(old.name()), "codec {}", old.name());
+    }
+}
+
+#[test]
+fn same_version_round_trips() {
+    let v2 = sample_data_v2();
+    for codec in codecs::<SampleDataV2>() {
+        assert_eq!(check(codec.as_ref(), codec.as_ref(), &v2, &v2), Compatibility::Compatible, "codec {}", codec.name());
+    }
+}
+
+#[test]
+fn decode_cost_recorded_only_for_readable_messages() {
+    for result in run(1) {
+        assert_eq!(result.decode_ns.is_some(), result.compatibility != Compatibility::Failed, "{result:?}");
+    }
+}
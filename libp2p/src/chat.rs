@@ -1,11 +1,14 @@
 use std::{collections::hash_map::DefaultHasher, error::Error, hash::{Hash, Hasher}, time::Duration};
 use futures::stream::StreamExt;
-use libp2p::{gossipsub, mdns, noise, swarm::{NetworkBehaviour, SwarmEvent}, tcp, yamux};
+use libp2p::{gossipsub, mdns, swarm::{NetworkBehaviour, SwarmEvent}};
 //modular stack for p2p netwrking 
 //yamux is use for multiplexing over a single connection
 use tokio::{io::{self, AsyncBufReadExt}, select};
 use tracing_subscriber::EnvFilter;
 
+use crate::config::{self, NodeConfig};
+use crate::transport;
+
 
 //combine the gossipsub and mdns behaviour
 #[derive(NetworkBehaviour)]
@@ -20,8 +23,9 @@ pub async fn main() -> Result<(), Box<dyn Error>> {
         .with_env_filter(EnvFilter::from_default_env())
         .init();
 
-    let mut swarm = libp2p::SwarmBuilder::with_new_identity().with_tokio() // genrate new identity
-        .with_tcp(tcp::Config::default(), noise::Config::new, yamux::Config::default)?
+    let config = NodeConfig::from_args()?;
+    let mut swarm = libp2p::SwarmBuilder::with_existing_identity(config.identity()?).with_tokio() // new identity unless --key is given
+        .with_other_transport(|key| transport::build(key, config.transports))?
         .with_behaviour(|key| {
 
             let gossipsub_config = gossipsub::ConfigBuilder::default()
@@ -33,7 +37,7 @@ pub async fn main() -> Result<(), Box<dyn Error>> {
                     gossipsub::MessageId::from(hasher.finish().to_string())
                 })
                 .build()
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                .map_err(io::Error::other)?;
 
             let gossipsub = gossipsub::Behaviour::new(
                 gossipsub::MessageAuthenticity::Signed(key.clone()),
@@ -48,7 +52,16 @@ pub async fn main() -> Result<(), Box<dyn Error>> {
 
     let topic = gossipsub::IdentTopic::new("test-net");
     swarm.behaviour_mut().gossipsub.subscribe(&topic)?;
-    swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
+    for addr in config.listen_addrs() {
+        swarm.listen_on(addr)?;
+    }
+    // peers beyond the LAN, mdns only finds local ones
+    for addr in &config.bootstrap {
+        if let Some(peer_id) = config::peer_id(addr) {
+            swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer_id);
+        }
+        swarm.dial(addr.clone())?;
+    }
 
 
     println!("Enter message: ");
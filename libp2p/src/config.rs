@@ -0,0 +1,150 @@
+use std::{
+    error::Error,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use libp2p::{identity::Keypair, multiaddr::Protocol, Multiaddr, PeerId};
+use serde::Deserialize;
+
+use crate::transport::{Transports, DEFAULT_TRANSPORTS};
+
+// how often Kademlia refreshes its routing table through the peers it knows
+pub const BOOTSTRAP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+// what a --config file may set, flags on the command line are added to it
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    key: Option<PathBuf>,
+    listen: Vec<String>,
+    bootstrap: Vec<String>,
+    transports: Vec<String>,
+    relay: Option<String>,
+}
+
+/// Identity, transports and addresses of a node, from `--config <file>`, `--key <file>`,
+/// `--transport <name>`, `--listen <multiaddr>` and `--bootstrap <multiaddr>`.
+pub struct NodeConfig {
+    pub key: Option<PathBuf>, // keypair file, created on first start
+    pub listen: Vec<Multiaddr>,
+    pub bootstrap: Vec<Multiaddr>, // peers dialed at startup, with /p2p/<peer id> for Kademlia
+    pub transports: Transports,
+    pub relay: Option<String>,     // relay mode from the config file, for nodes that run relays
+    pub args: Vec<String>,         // the other arguments, in order
+}
+
+impl NodeConfig {
+    pub fn from_args() -> Result<Self, Box<dyn Error>> {
+        Self::parse(std::env::args().skip(1))
+    }
+
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, Box<dyn Error>> {
+        let mut file = ConfigFile::default();
+        let (mut key, mut listen, mut bootstrap, mut rest) = (None, Vec::new(), Vec::new(), Vec::new());
+        let mut transports: Vec<String> = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--config" => {
+                    let path = args.next().ok_or("--config needs a file")?;
+                    let text = fs::read_to_string(&path).map_err(|e| format!("Failed to read {path}: {e}"))?;
+                    file = serde_json::from_str(&text).map_err(|e| format!("Invalid config {path}: {e}"))?;
+                }
+                "--key" => key = Some(PathBuf::from(args.next().ok_or("--key needs a file")?)),
+                "--listen" => listen.push(args.next().ok_or("--listen needs a multiaddr")?),
+                "--bootstrap" => bootstrap.push(args.next().ok_or("--bootstrap needs a multiaddr")?),
+                "--transport" => {
+                    let names = args.next().ok_or("--transport needs tcp, quic, ws or memory")?;
+                    transports.extend(names.split(',').map(|name| name.trim().to_string()));
+                }
+                _ => rest.push(arg),
+            }
+        }
+        file.listen.extend(listen);
+        file.bootstrap.extend(bootstrap);
+        // transports are a choice, the command line replaces the file's
+        if transports.is_empty() {
+            transports = file.transports;
+        }
+        let transports = if transports.is_empty() {
+            Transports::parse(DEFAULT_TRANSPORTS)?
+        } else {
+            Transports::parse(&transports)?
+        };
+        let config = NodeConfig {
+            key: key.or(file.key),
+            listen: parse_addrs(&file.listen)?,
+            bootstrap: parse_addrs(&file.bootstrap)?,
+            transports,
+            relay: file.relay,
+            args: rest,
+        };
+        if let Some(addr) = config.listen.iter().chain(&config.bootstrap).find(|addr| !transports.supports(addr)) {
+            return Err(format!("{addr} needs a transport that isn't enabled, see --transport").into());
+        }
+        Ok(config)
+    }
+
+    // the configured keypair, or a new identity for this run only
+    pub fn identity(&self) -> Result<Keypair, Box<dyn Error>> {
+        match &self.key {
+            Some(path) => keypair(path),
+            None => Ok(Keypair::generate_ed25519()),
+        }
+    }
+
+    // all interfaces on an OS-assigned port for every transport, unless listen addresses are configured
+    pub fn listen_addrs(&self) -> Vec<Multiaddr> {
+        if self.listen.is_empty() {
+            return self.transports.default_listen();
+        }
+        self.listen.clone()
+    }
+}
+
+fn parse_addrs(addrs: &[String]) -> Result<Vec<Multiaddr>, Box<dyn Error>> {
+    addrs
+        .iter()
+        .map(|addr| addr.parse().map_err(|e| format!("Invalid multiaddr {addr}: {e}").into()))
+        .collect()
+}
+
+// the peer an address ends with, Kademlia only takes addresses of known peers
+pub fn peer_id(addr: &Multiaddr) -> Option<PeerId> {
+    match addr.iter().last()? {
+        Protocol::P2p(peer) => Some(peer),
+        _ => None,
+    }
+}
+
+// load the keypair kept in `path`, or create one there on first start
+pub fn keypair(path: &Path) -> Result<Keypair, Box<dyn Error>> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Keypair::from_protobuf_encoding(&bytes)
+            .map_err(|e| format!("Invalid keypair in {}: {e}", path.display()))?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let keypair = Keypair::generate_ed25519();
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            write_private(path, &keypair.to_protobuf_encoding()?)?;
+            println!("Created a new identity in {}", path.display());
+            Ok(keypair)
+        }
+        Err(e) => Err(format!("Failed to read {}: {e}", path.display()).into()),
+    }
+}
+
+// the key file is readable by its owner only
+#[cfg(unix)]
+fn write_private(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    use std::os::unix::fs::OpenOptionsExt;
+    fs::OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)?.write_all(bytes)
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    fs::OpenOptions::new().write(true).create_new(true).open(path)?.write_all(bytes)
+}
@@ -1,19 +1,22 @@
 use std::{error::Error, fs, path::Path};
+use base64::{engine::general_purpose::STANDARD, Engine};
 use futures::stream::StreamExt;
 use libp2p::{
     kad,
-    kad::{store::MemoryStore, Mode, Quorum},
-    mdns, noise,
+    kad::{Mode, Quorum},
+    mdns,
     swarm::{NetworkBehaviour, SwarmEvent},
-    tcp, yamux, PeerId,
+    PeerId,
 };
 use tokio::{
     io::{self, AsyncBufReadExt},
     select,
 };
-use sled;
 use tracing_subscriber::EnvFilter;
 use std::collections::HashSet;
+use crate::config::{self, NodeConfig};
+use crate::transport;
+use crate::sled_store::SledStore;
 
 #[tokio::main]
 pub async fn main() -> Result<(), Box<dyn Error>> {
@@ -21,24 +24,28 @@ pub async fn main() -> Result<(), Box<dyn Error>> {
         .with_env_filter(EnvFilter::from_default_env())
         .try_init();
 
+    // Open Sled database
+    let config = NodeConfig::from_args()?;
+    let db_path = config.args.first().cloned().unwrap_or_else(|| "file_store/db".to_string());
+    let db = sled::open(&db_path).expect("Failed to open sled database");
+    // the peer ID stays the same across restarts, next to the records it provides
+    let key_path = config.key.clone().unwrap_or_else(|| format!("{db_path}.key").into());
+
     #[derive(NetworkBehaviour)]
     struct Behaviour {
-        kademlia: kad::Behaviour<MemoryStore>,
+        kademlia: kad::Behaviour<SledStore>,
         mdns: mdns::tokio::Behaviour,
     }
 
-    let mut swarm = libp2p::SwarmBuilder::with_new_identity()
+    let mut swarm = libp2p::SwarmBuilder::with_existing_identity(config::keypair(&key_path)?)
         .with_tokio()
-        .with_tcp(
-            tcp::Config::default(),
-            noise::Config::new,
-            yamux::Config::default,
-        )?
+        .with_other_transport(|key| transport::build(key, config.transports))?
         .with_behaviour(|key| {
             Ok(Behaviour {
                 kademlia: kad::Behaviour::new(
                     key.public().to_peer_id(),
-                    MemoryStore::new(key.public().to_peer_id()),
+                    // DHT records are kept in sled too, so they survive a restart
+                    SledStore::new(key.public().to_peer_id(), &db)?,
                 ),
                 mdns: mdns::tokio::Behaviour::new(
                     mdns::Config::default(),
@@ -52,18 +59,25 @@ pub async fn main() -> Result<(), Box<dyn Error>> {
 
     let mut stdin = io::BufReader::new(io::stdin()).lines();
 
-    swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
+    for addr in config.listen_addrs() {
+        swarm.listen_on(addr)?;
+    }
+    for addr in &config.bootstrap {
+        match config::peer_id(addr) {
+            Some(peer_id) => {
+                swarm.behaviour_mut().kademlia.add_address(&peer_id, addr.clone());
+            }
+            None => eprintln!("Bootstrap address {addr} has no /p2p/<peer id>, dialing it anyway"),
+        }
+        swarm.dial(addr.clone())?;
+    }
+    let mut bootstrap = tokio::time::interval(config::BOOTSTRAP_INTERVAL);
     println!("Local Peer ID: {}", swarm.local_peer_id());
     println!("PUT_FILE <key> <file_path>: Store a file in the DHT");
     println!("GET_FILE <key>: Retrieve a file from the DHT");
     println!("LIST_FILE: List all files stored in the DHT");
     println!("LIST_PEERS: List all connected peers");
 
-
-    // Open Sled database
-    let db_path = std::env::args().nth(1).unwrap_or_else(|| "file_store/db".to_string());
-    let db = sled::open(db_path).expect("Failed to open sled database");
-
     // Store discovered peers
     let mut peers: HashSet<PeerId> = HashSet::new();
 
@@ -72,6 +86,9 @@ pub async fn main() -> Result<(), Box<dyn Error>> {
             Ok(Some(line)) = stdin.next_line() => {
                 handle_input_line(&mut swarm.behaviour_mut().kademlia, &db, &peers, line);
             }
+            _ = bootstrap.tick() => {
+                let _ = swarm.behaviour_mut().kademlia.bootstrap();
+            }
             event = swarm.select_next_some() => match event {
                 SwarmEvent::NewListenAddr { address, .. } => {
                     println!("Listening on {address:?}");
@@ -99,7 +116,7 @@ pub async fn main() -> Result<(), Box<dyn Error>> {
 
                             // Also save as a file
                             if let Err(e) = fs::write(&filename, &value) {
-                                // eprintln!("Failed to save file: {e}");
+                                eprintln!("Failed to save file: {e}");
                             } else {
                                 println!("File retrieved and saved as: {}", filename);
                             }
@@ -126,7 +143,7 @@ pub async fn main() -> Result<(), Box<dyn Error>> {
 }
 
 //Handles user input commands
-fn handle_input_line(kademlia: &mut kad::Behaviour<MemoryStore>,db: &sled::Db,peers: &HashSet<PeerId>,line: String) 
+fn handle_input_line(kademlia: &mut kad::Behaviour<SledStore>,db: &sled::Db,peers: &HashSet<PeerId>,line: String) 
 {
     let mut args = line.split_whitespace();
 
@@ -144,10 +161,10 @@ fn handle_input_line(kademlia: &mut kad::Behaviour<MemoryStore>,db: &sled::Db,pe
                         let record_key = kad::RecordKey::new(&key);
                         let filename = path.file_name().unwrap().to_string_lossy();
 
-                        let file_data = format!("{}|{}", filename, base64::encode(&file_bytes));
+                        let file_data = format!("{}|{}", filename, STANDARD.encode(&file_bytes));
 
                         // Store in sled first
-                        if let Err(e) = db.insert(&key, file_data.as_bytes()) {
+                        if let Err(e) = db.insert(key, file_data.as_bytes()) {
                             eprintln!("Failed to store file in sled: {e}");
                             return;
                         }
@@ -175,7 +192,7 @@ fn handle_input_line(kademlia: &mut kad::Behaviour<MemoryStore>,db: &sled::Db,pe
                 let record_key = kad::RecordKey::new(&key);
 
                 // Check in sled first
-                if let Ok(Some(file_bytes)) = db.get(&key) {
+                if let Ok(Some(file_bytes)) = db.get(key) {
                     let filename = format!("retrieved_{}", key);
                     if let Err(e) = fs::write(&filename, &file_bytes) {
                         eprintln!("Failed to save file: {e}");
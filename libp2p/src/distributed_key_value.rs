@@ -3,9 +3,8 @@ use futures::stream::StreamExt;
 use libp2p::{
     kad,
     kad::{store::MemoryStore, Mode},
-    mdns, noise,
+    mdns,
     swarm::{NetworkBehaviour, SwarmEvent},
-    tcp, yamux,
 };
 use tokio::{
     io::{self, AsyncBufReadExt},
@@ -13,6 +12,9 @@ use tokio::{
 };
 use tracing_subscriber::EnvFilter;
 
+use crate::config::{self, NodeConfig};
+use crate::transport;
+
 #[tokio::main]
 pub async fn main() -> Result<(), Box<dyn Error>> {
     let _ = tracing_subscriber::fmt()
@@ -26,13 +28,10 @@ pub async fn main() -> Result<(), Box<dyn Error>> {
         mdns: mdns::tokio::Behaviour,
     }
 
-    let mut swarm = libp2p::SwarmBuilder::with_new_identity()
+    let config = NodeConfig::from_args()?;
+    let mut swarm = libp2p::SwarmBuilder::with_existing_identity(config.identity()?)
         .with_tokio()
-        .with_tcp(
-            tcp::Config::default(),
-            noise::Config::new,
-            yamux::Config::default,
-        )?
+        .with_other_transport(|key| transport::build(key, config.transports))?
         .with_behaviour(|key| {
             Ok(Behaviour {
                 kademlia: kad::Behaviour::new(
@@ -52,8 +51,22 @@ pub async fn main() -> Result<(), Box<dyn Error>> {
     // Read full lines from stdin
     let mut stdin = io::BufReader::new(io::stdin()).lines();
 
-    // Listen on all interfaces and whatever port the OS assigns.
-    swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
+    // Listen on all interfaces and whatever port the OS assigns, unless --listen says otherwise.
+    for addr in config.listen_addrs() {
+        swarm.listen_on(addr)?;
+    }
+    // Bootstrap peers let the DHT reach beyond the LAN, mDNS only finds local peers.
+    for addr in &config.bootstrap {
+        match config::peer_id(addr) {
+            Some(peer_id) => {
+                swarm.behaviour_mut().kademlia.add_address(&peer_id, addr.clone());
+            }
+            None => eprintln!("Bootstrap address {addr} has no /p2p/<peer id>, dialing it anyway"),
+        }
+        swarm.dial(addr.clone())?;
+    }
+    // Refresh the routing table now and then, the first tick runs right away.
+    let mut bootstrap = tokio::time::interval(config::BOOTSTRAP_INTERVAL);
     println!("Local Peer ID: {}", swarm.local_peer_id());
     println!("GET <key>: Get a value from the DHT");
     println!("GET_PROVIDER <key>: Get providers for a key from the DHT");
@@ -67,6 +80,10 @@ pub async fn main() -> Result<(), Box<dyn Error>> {
         Ok(Some(line)) = stdin.next_line() => {
             handle_input_line(&mut swarm.behaviour_mut().kademlia, line);
         }
+        _ = bootstrap.tick() => {
+            // nothing to do until a peer is known
+            let _ = swarm.behaviour_mut().kademlia.bootstrap();
+        }
         event = swarm.select_next_some() => match event {
             SwarmEvent::NewListenAddr { address, .. } => {
                 println!("Listening in {address:?}");
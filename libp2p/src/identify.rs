@@ -1,21 +1,21 @@
 use std::error::Error;
 use futures::StreamExt;
-use libp2p::{core::multiaddr::Multiaddr, identify, noise, swarm::SwarmEvent, tcp, yamux};
+use libp2p::{core::multiaddr::Multiaddr, identify, swarm::SwarmEvent};
 use tracing_subscriber::EnvFilter;
 
+use crate::config::NodeConfig;
+use crate::transport;
+
 #[tokio::main]
 pub async fn main() -> Result<(), Box<dyn Error>> {
     let _ = tracing_subscriber::fmt()
         .with_env_filter(EnvFilter::from_default_env())
         .try_init();
 
-    let mut swarm = libp2p::SwarmBuilder::with_new_identity()
+    let config = NodeConfig::from_args()?;
+    let mut swarm = libp2p::SwarmBuilder::with_existing_identity(config.identity()?)
         .with_tokio()
-        .with_tcp(
-            tcp::Config::default(),
-            noise::Config::new,
-            yamux::Config::default,
-        )?
+        .with_other_transport(|key| transport::build(key, config.transports))?
         .with_behaviour(|key| {
             identify::Behaviour::new(identify::Config::new(
                 "/ipfs/id/1.0.0".to_string(),
@@ -24,13 +24,17 @@ pub async fn main() -> Result<(), Box<dyn Error>> {
         })?
         .build();
 
-    swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
+    for addr in config.listen_addrs() {
+        swarm.listen_on(addr)?;
+    }
 
-    
-    if let Some(addr) = std::env::args().nth(1) {
-        let remote: Multiaddr = addr.parse()?;
+    let mut remotes = config.bootstrap.clone();
+    if let Some(addr) = config.args.first() {
+        remotes.push(addr.parse::<Multiaddr>()?);
+    }
+    for remote in remotes {
+        println!("Dialed {remote}");
         swarm.dial(remote)?;
-        println!("Dialed {addr}")
     }
 
     loop {
@@ -0,0 +1,3 @@
+pub mod config;
+pub mod sled_store;
+pub mod transport;
@@ -6,6 +6,8 @@ mod distributed_key_value;
 mod distributed_key_file;
 
 use std::io;
+// shared with the integration tests in tests/
+use libp2p_examples::{config, sled_store, transport};
 fn main()
 {
     println!("Enter \n1.Ping\n2.Identify\n3.Chat\n4.Distributed Key Value\n5.Distributed Key File");
@@ -1,35 +1,40 @@
-use std::{error::Error, time::Duration};
+use std::error::Error;
 
 use futures::prelude::*;
-use libp2p::{noise, ping, swarm::SwarmEvent, tcp, yamux, Multiaddr};
+use libp2p::{ping, swarm::SwarmEvent, Multiaddr};
 use tracing_subscriber::EnvFilter;
 
+use crate::config::NodeConfig;
+use crate::transport;
+
 #[tokio::main]
 pub async fn main() -> Result<(), Box<dyn Error>> {
     let _ = tracing_subscriber::fmt()
         .with_env_filter(EnvFilter::from_default_env())
         .try_init();
 
-    let mut swarm = libp2p::SwarmBuilder::with_new_identity()
+    let config = NodeConfig::from_args()?;
+    let mut swarm = libp2p::SwarmBuilder::with_existing_identity(config.identity()?)
         .with_tokio()
-        .with_tcp(
-            tcp::Config::default(),
-            noise::Config::new,
-            yamux::Config::default,
-        )?
+        .with_other_transport(|key| transport::build(key, config.transports))?
         .with_behaviour(|_| ping::Behaviour::default())?
         .build();
 
     // Tell the swarm to listen on all interfaces and a random, OS-assigned
-    // port.
-    swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
+    // port, unless --listen says otherwise.
+    for addr in config.listen_addrs() {
+        swarm.listen_on(addr)?;
+    }
 
-    // Dial the peer identified by the multi-address given as the second
-    // command-line argument, if any.
-    if let Some(addr) = std::env::args().nth(1) {
-        let remote: Multiaddr = addr.parse()?;
+    // Dial the peer identified by the multi-address given as the first
+    // command-line argument, if any, and the --bootstrap peers.
+    let mut remotes = config.bootstrap.clone();
+    if let Some(addr) = config.args.first() {
+        remotes.push(addr.parse::<Multiaddr>()?);
+    }
+    for remote in remotes {
+        println!("Dialed {remote}");
         swarm.dial(remote)?;
-        println!("Dialed {addr}")
     }
 
     loop {
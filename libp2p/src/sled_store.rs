@@ -0,0 +1,352 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use libp2p::{
+    kad::{
+        store::{Error, RecordStore, Result},
+        KBucketKey, ProviderRecord, Record, RecordKey, K_VALUE,
+    },
+    Multiaddr, PeerId,
+};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+#[derive(Debug, Clone)]
+pub struct SledStoreConfig {
+    pub max_records: usize,
+    pub max_value_bytes: usize,
+    pub max_providers_per_key: usize,
+    pub max_provided_keys: usize,
+}
+
+impl Default for SledStoreConfig {
+    // same value and provider limits as MemoryStore, but disk holds far more records
+    fn default() -> Self {
+        SledStoreConfig {
+            max_records: 64 * 1024,
+            max_value_bytes: 65 * 1024,
+            max_providers_per_key: K_VALUE.get(),
+            max_provided_keys: 64 * 1024,
+        }
+    }
+}
+
+// on-disk form of a record, expiry as unix millis because `Instant` doesn't survive a restart
+#[derive(Serialize, Deserialize)]
+struct StoredRecord {
+    value: Vec<u8>,
+    publisher: Option<Vec<u8>>,
+    expires: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredProvider {
+    provider: Vec<u8>,
+    expires: Option<u64>,
+    addresses: Vec<Vec<u8>>,
+}
+
+fn to_unix_ms(instant: Instant) -> u64 {
+    let now = Instant::now();
+    let system = if instant >= now {
+        SystemTime::now() + (instant - now)
+    } else {
+        SystemTime::now() - (now - instant)
+    };
+    system.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+fn from_unix_ms(ms: u64) -> Instant {
+    let target = UNIX_EPOCH + Duration::from_millis(ms);
+    match target.duration_since(SystemTime::now()) {
+        Ok(ahead) => Instant::now() + ahead,
+        Err(behind) => Instant::now().checked_sub(behind.duration()).unwrap_or_else(Instant::now),
+    }
+}
+
+/// Kademlia record store kept in sled, so records and provider records survive a restart.
+pub struct SledStore {
+    local_key: KBucketKey<PeerId>,
+    config: SledStoreConfig,
+    records: sled::Tree,
+    providers: sled::Tree,
+    record_count: usize,
+    provided: HashSet<RecordKey>, // keys we provide ourselves
+    held: HashMap<PeerId, usize>, // records published by each peer and its provider records
+}
+
+impl SledStore {
+    pub fn new(local_id: PeerId, db: &sled::Db) -> sled::Result<Self> {
+        Self::with_config(local_id, db, SledStoreConfig::default())
+    }
+
+    pub fn with_config(local_id: PeerId, db: &sled::Db, config: SledStoreConfig) -> sled::Result<Self> {
+        let records = db.open_tree("kad_records")?;
+        let providers = db.open_tree("kad_providers")?;
+
+        let mut store = SledStore {
+            local_key: KBucketKey::from(local_id),
+            config,
+            record_count: records.len(),
+            records,
+            providers,
+            provided: HashSet::new(),
+            held: HashMap::new(),
+        };
+        for (key, bytes) in store.records.iter().filter_map(|entry| entry.ok()) {
+            if let Some(publisher) = Self::decode_record(&RecordKey::from(key.to_vec()), &bytes).and_then(|r| r.publisher) {
+                store.hold(publisher);
+            }
+        }
+        let now = Instant::now();
+        for key in store.providers.iter().keys().filter_map(|k| k.ok()) {
+            let key = RecordKey::from(key.to_vec());
+            let mut providers = store.load_providers(&key);
+            let before = providers.len();
+            providers.retain(|p| !p.is_expired(now));
+            if providers.len() != before {
+                let _ = store.save_providers(&key, &providers);
+            }
+            if providers.iter().any(|p| p.provider == local_id) {
+                store.provided.insert(key.clone());
+            }
+            for p in &providers {
+                store.hold(p.provider);
+            }
+        }
+        Ok(store)
+    }
+
+    /// Records published by `peer` and provider records of `peer` held here, for a node that
+    /// caps what one peer may store. Expired provider records count until their key is
+    /// announced again or the store is reopened.
+    pub fn held_for(&self, peer: &PeerId) -> usize {
+        self.held.get(peer).copied().unwrap_or(0)
+    }
+
+    fn hold(&mut self, peer: PeerId) {
+        *self.held.entry(peer).or_default() += 1;
+    }
+
+    fn release(&mut self, peer: &PeerId) {
+        if let Some(count) = self.held.get_mut(peer) {
+            *count -= 1;
+            if *count == 0 {
+                self.held.remove(peer);
+            }
+        }
+    }
+
+    fn decode_record(key: &RecordKey, bytes: &[u8]) -> Option<Record> {
+        let stored: StoredRecord = bincode::deserialize(bytes).ok()?;
+        Some(Record {
+            key: key.clone(),
+            value: stored.value,
+            publisher: stored.publisher.and_then(|p| PeerId::from_bytes(&p).ok()),
+            expires: stored.expires.map(from_unix_ms),
+        })
+    }
+
+    fn load_providers(&self, key: &RecordKey) -> Vec<ProviderRecord> {
+        let bytes = match self.providers.get(key.as_ref()) {
+            Ok(Some(bytes)) => bytes,
+            Ok(None) => return Vec::new(),
+            Err(e) => {
+                error!("Failed to read provider records from sled: {e}");
+                return Vec::new();
+            }
+        };
+        let stored: Vec<StoredProvider> = bincode::deserialize(&bytes).unwrap_or_default();
+        stored
+            .into_iter()
+            .filter_map(|p| {
+                Some(ProviderRecord {
+                    key: key.clone(),
+                    provider: PeerId::from_bytes(&p.provider).ok()?,
+                    expires: p.expires.map(from_unix_ms),
+                    addresses: p.addresses.into_iter().filter_map(|a| Multiaddr::try_from(a).ok()).collect(),
+                })
+            })
+            .collect()
+    }
+
+    fn save_providers(&self, key: &RecordKey, providers: &[ProviderRecord]) -> Result<()> {
+        let result = if providers.is_empty() {
+            self.providers.remove(key.as_ref()).map(|_| ())
+        } else {
+            let stored: Vec<StoredProvider> = providers
+                .iter()
+                .map(|p| StoredProvider {
+                    provider: p.provider.to_bytes(),
+                    expires: p.expires.map(to_unix_ms),
+                    addresses: p.addresses.iter().map(|a| a.to_vec()).collect(),
+                })
+                .collect();
+            let bytes = bincode::serialize(&stored).expect("provider records serialize");
+            self.providers.insert(key.as_ref(), bytes).map(|_| ())
+        };
+        result.map_err(|e| write_failed("provider records", e))
+    }
+}
+
+// kad's store errors have no I/O variant, the write is refused as if the store were full.
+// The real error is logged, so a failing disk isn't taken for a full store
+fn write_failed(what: &str, e: sled::Error) -> Error {
+    error!("Failed to write {what} to sled: {e}");
+    Error::MaxRecords
+}
+
+impl RecordStore for SledStore {
+    type RecordsIter<'a> = std::vec::IntoIter<Cow<'a, Record>>;
+    type ProvidedIter<'a> = std::vec::IntoIter<Cow<'a, ProviderRecord>>;
+
+    fn get(&self, k: &RecordKey) -> Option<Cow<'_, Record>> {
+        let bytes = match self.records.get(k.as_ref()) {
+            Ok(bytes) => bytes?,
+            Err(e) => {
+                error!("Failed to read record from sled: {e}");
+                return None;
+            }
+        };
+        let record = Self::decode_record(k, &bytes)?;
+        if record.is_expired(Instant::now()) {
+            return None; // kad removes it through `remove`
+        }
+        Some(Cow::Owned(record))
+    }
+
+    fn put(&mut self, r: Record) -> Result<()> {
+        if r.value.len() >= self.config.max_value_bytes {
+            return Err(Error::ValueTooLarge);
+        }
+
+        let old = self.records.get(r.key.as_ref()).map_err(|e| write_failed("record", e))?;
+        if old.is_none() && self.record_count >= self.config.max_records {
+            return Err(Error::MaxRecords);
+        }
+
+        let publisher = r.publisher;
+        let stored = StoredRecord {
+            value: r.value,
+            publisher: publisher.map(|p| p.to_bytes()),
+            expires: r.expires.map(to_unix_ms),
+        };
+        let bytes = bincode::serialize(&stored).expect("record serializes");
+        self.records.insert(r.key.as_ref(), bytes).map_err(|e| write_failed("record", e))?;
+        match old {
+            Some(old) => {
+                if let Some(previous) = Self::decode_record(&r.key, &old).and_then(|r| r.publisher) {
+                    self.release(&previous);
+                }
+            }
+            None => self.record_count += 1,
+        }
+        if let Some(publisher) = publisher {
+            self.hold(publisher);
+        }
+        Ok(())
+    }
+
+    fn remove(&mut self, k: &RecordKey) {
+        match self.records.remove(k.as_ref()) {
+            Ok(Some(bytes)) => {
+                self.record_count -= 1;
+                if let Some(publisher) = Self::decode_record(k, &bytes).and_then(|r| r.publisher) {
+                    self.release(&publisher);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => error!("Failed to remove record from sled: {e}"),
+        }
+    }
+
+    fn records(&self) -> Self::RecordsIter<'_> {
+        self.records
+            .iter()
+            .filter_map(|entry| entry.ok())
+            .filter_map(|(key, bytes)| Self::decode_record(&RecordKey::from(key.to_vec()), &bytes))
+            .map(Cow::Owned)
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    fn add_provider(&mut self, record: ProviderRecord) -> Result<()> {
+        let mut providers = self.load_providers(&record.key);
+        let is_local = record.provider == *self.local_key.preimage();
+        // expired providers go while we're at it, they stop counting against their peers
+        let now = Instant::now();
+        let mut released: Vec<PeerId> = providers.iter().filter(|p| p.is_expired(now)).map(|p| p.provider).collect();
+        providers.retain(|p| !p.is_expired(now));
+        let mut added = false;
+
+        if let Some(existing) = providers.iter_mut().find(|p| p.provider == record.provider) {
+            // refresh expiry and addresses
+            *existing = record.clone();
+        } else {
+            if is_local && self.provided.len() >= self.config.max_provided_keys {
+                return Err(Error::MaxProvidedKeys);
+            }
+            if providers.len() < self.config.max_providers_per_key {
+                providers.push(record.clone());
+                added = true;
+            } else {
+                // full, keep the providers closest to us like MemoryStore does
+                let distance = |p: &PeerId| self.local_key.distance(&KBucketKey::from(*p));
+                let farthest = providers
+                    .iter()
+                    .enumerate()
+                    .max_by_key(|(_, p)| distance(&p.provider))
+                    .map(|(i, p)| (i, distance(&p.provider)));
+                match farthest {
+                    Some((i, far)) if distance(&record.provider) < far => {
+                        released.push(providers[i].provider);
+                        providers[i] = record.clone();
+                        added = true;
+                    }
+                    _ => return Ok(()), // nothing expired either, or there would be room
+                }
+            }
+        }
+
+        self.save_providers(&record.key, &providers)?;
+        for peer in &released {
+            self.release(peer);
+        }
+        if added {
+            self.hold(record.provider);
+        }
+        if is_local {
+            self.provided.insert(record.key);
+        }
+        Ok(())
+    }
+
+    fn providers(&self, key: &RecordKey) -> Vec<ProviderRecord> {
+        let now = Instant::now();
+        self.load_providers(key).into_iter().filter(|p| !p.is_expired(now)).collect()
+    }
+
+    fn provided(&self) -> Self::ProvidedIter<'_> {
+        let local = self.local_key.preimage();
+        self.provided
+            .iter()
+            .flat_map(|key| self.load_providers(key).into_iter().filter(|p| p.provider == *local))
+            .map(Cow::Owned)
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    fn remove_provider(&mut self, k: &RecordKey, p: &PeerId) {
+        let mut providers = self.load_providers(k);
+        let before = providers.len();
+        providers.retain(|record| record.provider != *p);
+        if providers.len() != before && self.save_providers(k, &providers).is_ok() {
+            self.release(p);
+        }
+        if p == self.local_key.preimage() {
+            self.provided.remove(k);
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use std::error::Error;
+use libp2p::{
+    core::{
+        muxing::StreamMuxerBox,
+        transport::{Boxed, MemoryTransport},
+        upgrade, Transport,
+    },
+    dns,
+    identity::Keypair,
+    multiaddr::Protocol,
+    noise, quic, tcp, websocket, yamux, Multiaddr, PeerId,
+};
+
+// used unless `transports` or --transport say otherwise
+pub const DEFAULT_TRANSPORTS: &[&str] = &["tcp", "quic"];
+
+type Connection = (PeerId, StreamMuxerBox);
+
+/// Which transports the swarm runs. TCP, WebSocket and memory connections are secured with
+/// noise and multiplexed with yamux, QUIC brings both itself.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Transports {
+    pub tcp: bool,
+    pub quic: bool,
+    pub websocket: bool,
+    pub memory: bool, // in-process only, for nodes running side by side in tests
+}
+
+impl Transports {
+    // names as written in the config file: tcp, quic, ws and memory
+    pub fn parse<S: AsRef<str>>(names: &[S]) -> Result<Self, String> {
+        let mut transports = Transports::default();
+        for name in names {
+            match name.as_ref() {
+                "tcp" => transports.tcp = true,
+                "quic" => transports.quic = true,
+                "ws" | "websocket" => transports.websocket = true,
+                "memory" => transports.memory = true,
+                other => return Err(format!("unknown transport {other}, use tcp, quic, ws or memory")),
+            }
+        }
+        if !(transports.tcp || transports.quic || transports.websocket || transports.memory) {
+            return Err("at least one transport is needed".to_string());
+        }
+        Ok(transports)
+    }
+
+    // one address per transport, all interfaces and an OS-assigned port, a random one for memory
+    pub fn default_listen(&self) -> Vec<Multiaddr> {
+        let mut addrs = Vec::new();
+        if self.tcp {
+            addrs.push("/ip4/0.0.0.0/tcp/0");
+        }
+        if self.quic {
+            addrs.push("/ip4/0.0.0.0/udp/0/quic-v1");
+        }
+        if self.websocket {
+            addrs.push("/ip4/0.0.0.0/tcp/0/ws");
+        }
+        if self.memory {
+            addrs.push("/memory/0");
+        }
+        addrs.into_iter().map(|addr| addr.parse().expect("valid multiaddr")).collect()
+    }
+
+    // whether an address can be listened on or dialed with these transports
+    pub fn supports(&self, addr: &Multiaddr) -> bool {
+        let mut tcp = false;
+        for protocol in addr.iter() {
+            match protocol {
+                Protocol::QuicV1 => return self.quic,
+                Protocol::Ws(_) | Protocol::Wss(_) => return self.websocket,
+                Protocol::Memory(_) => return self.memory,
+                Protocol::Tcp(_) => tcp = true,
+                _ => {}
+            }
+        }
+        tcp && self.tcp
+    }
+}
+
+// the enabled transports as one, for `SwarmBuilder::with_other_transport`
+pub fn build(key: &Keypair, transports: Transports) -> Result<Boxed<Connection>, Box<dyn Error + Send + Sync>> {
+    let mut built: Vec<Boxed<Connection>> = Vec::new();
+    if transports.tcp {
+        built.push(
+            tcp::tokio::Transport::new(tcp::Config::default())
+                .upgrade(upgrade::Version::V1Lazy)
+                .authenticate(noise::Config::new(key)?)
+                .multiplex(yamux::Config::default())
+                .map(|(peer, muxer), _| (peer, StreamMuxerBox::new(muxer)))
+                .boxed(),
+        );
+    }
+    if transports.quic {
+        built.push(
+            quic::tokio::Transport::new(quic::Config::new(key))
+                .map(|(peer, connection), _| (peer, StreamMuxerBox::new(connection)))
+                .boxed(),
+        );
+    }
+    if transports.websocket {
+        // /dns addresses are common for WebSocket endpoints
+        let tcp = dns::tokio::Transport::system(tcp::tokio::Transport::new(tcp::Config::default()))?;
+        built.push(
+            websocket::Config::new(tcp)
+                .upgrade(upgrade::Version::V1Lazy)
+                .authenticate(noise::Config::new(key)?)
+                .multiplex(yamux::Config::default())
+                .map(|(peer, muxer), _| (peer, StreamMuxerBox::new(muxer)))
+                .boxed(),
+        );
+    }
+    if transports.memory {
+        built.push(
+            MemoryTransport::default()
+                .upgrade(upgrade::Version::V1Lazy)
+                .authenticate(noise::Config::new(key)?)
+                .multiplex(yamux::Config::default())
+                .map(|(peer, muxer), _| (peer, StreamMuxerBox::new(muxer)))
+                .boxed(),
+        );
+    }
+    built
+        .into_iter()
+        .reduce(|a, b| a.or_transport(b).map(|either, _| either.into_inner()).boxed())
+        .ok_or_else(|| "no transport enabled".into())
+}
@@ -0,0 +1,355 @@
+// Kademlia over SledStore on swarms running in the test process, connected over the memory
+// transport to the peers a test gives them and nothing else
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use futures::StreamExt;
+use libp2p::{
+    identify,
+    identity::Keypair,
+    kad::{self, Mode, QueryId, QueryResult, Quorum, Record, RecordKey},
+    multiaddr::Protocol,
+    swarm::{NetworkBehaviour, SwarmEvent},
+    Multiaddr, PeerId,
+};
+use libp2p_examples::{
+    sled_store::SledStore,
+    transport::{self, Transports},
+};
+use tokio::{
+    select,
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+    time,
+};
+
+// every wait in a test gives up after this long
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+// tests run in parallel in one process, each gets its own directory
+static NETWORKS: AtomicUsize = AtomicUsize::new(0);
+
+type Kademlia = kad::Behaviour<SledStore>;
+
+#[derive(NetworkBehaviour)]
+struct Behaviour {
+    kademlia: Kademlia,
+    identify: identify::Behaviour, // tells peers we speak Kademlia, so they route to us
+}
+
+// what a test asks of a swarm's task
+enum Action {
+    Call(Box<dyn FnOnce(&mut Kademlia) + Send>),
+    Query(Box<dyn FnOnce(&mut Kademlia) -> QueryId + Send>, oneshot::Sender<QueryResult>),
+}
+
+/// The peers of one test, each with a sled database in a directory of their own. `stop` removes
+/// it, a test that fails before then leaves it behind to look into.
+struct Network {
+    dir: PathBuf,
+    started: usize,
+    tasks: HashMap<PeerId, JoinHandle<()>>,
+}
+
+impl Network {
+    fn new() -> Self {
+        let id = NETWORKS.fetch_add(1, Ordering::SeqCst);
+        let dir = std::env::temp_dir().join(format!("libp2p-test-{}-{id}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("create the test directory");
+        Network { dir, started: 0, tasks: HashMap::new() }
+    }
+
+    // a new identity and an empty database
+    async fn start(&mut self, peers: &[&Peer]) -> Peer {
+        let dir = self.dir.join(format!("peer{}", self.started));
+        self.started += 1;
+        self.run(Keypair::generate_ed25519(), dir, peers).await
+    }
+
+    /// Stops the peer as if it crashed and starts it again with the same identity and database.
+    async fn restart(&mut self, peer: Peer, peers: &[&Peer]) -> Peer {
+        let Peer { key, dir, .. } = peer;
+        self.stopped(key.public().to_peer_id()).await;
+        self.run(key, dir, peers).await
+    }
+
+    /// Stops the peer as if it crashed, its connections close without a goodbye.
+    async fn kill(&mut self, peer: Peer) {
+        self.stopped(peer.peer_id).await;
+    }
+
+    // once its task is over nothing holds its database any more
+    async fn stopped(&mut self, peer: PeerId) {
+        let task = self.tasks.remove(&peer).expect("a running peer");
+        task.abort();
+        let _ = time::timeout(TIMEOUT, task).await;
+    }
+
+    /// Stops every peer and removes the directory.
+    async fn stop(mut self) {
+        let peers: Vec<PeerId> = self.tasks.keys().copied().collect();
+        for peer in peers {
+            self.stopped(peer).await;
+        }
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+
+    // bootstrapped to `peers`, connected to all of them and in their routing tables once this returns
+    async fn run(&mut self, key: Keypair, dir: PathBuf, peers: &[&Peer]) -> Peer {
+        // sled's flusher from a stopped peer may hold the lock for a moment after a restart
+        let opened = time::timeout(TIMEOUT, async {
+            loop {
+                match sled::open(&dir) {
+                    Ok(db) => return db,
+                    Err(_) => time::sleep(Duration::from_millis(50)).await,
+                }
+            }
+        });
+        let db = opened.await.expect("open the database in time");
+        let transports = Transports::parse(&["memory"]).unwrap();
+        let mut swarm = libp2p::SwarmBuilder::with_existing_identity(key.clone())
+            .with_tokio()
+            .with_other_transport(|key| transport::build(key, transports))
+            .unwrap()
+            .with_behaviour(|key| {
+                let peer_id = key.public().to_peer_id();
+                Ok(Behaviour {
+                    kademlia: kad::Behaviour::new(peer_id, SledStore::new(peer_id, &db)?),
+                    identify: identify::Behaviour::new(identify::Config::new("/ipfs/id/1.0.0".to_string(), key.public())),
+                })
+            })
+            .unwrap()
+            .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
+            .build();
+        let peer_id = *swarm.local_peer_id();
+        swarm.behaviour_mut().kademlia.set_mode(Some(Mode::Server));
+        swarm.listen_on("/memory/0".parse().unwrap()).unwrap();
+        let listening = time::timeout(TIMEOUT, async {
+            loop {
+                if let SwarmEvent::NewListenAddr { address, .. } = swarm.select_next_some().await {
+                    return address.with(Protocol::P2p(peer_id));
+                }
+            }
+        });
+        let addr = listening.await.expect("listen in time");
+        for peer in peers {
+            swarm.behaviour_mut().kademlia.add_address(&peer.peer_id, peer.addr.clone());
+            swarm.dial(peer.addr.clone()).expect("dial the peer");
+        }
+
+        let (actions, mut pending) = mpsc::unbounded_channel();
+        let task = tokio::spawn(async move {
+            let mut queries = HashMap::new();
+            loop {
+                select! {
+                    Some(action) = pending.recv() => match action {
+                        Action::Call(call) => call(&mut swarm.behaviour_mut().kademlia),
+                        Action::Query(start, done) => {
+                            queries.insert(start(&mut swarm.behaviour_mut().kademlia), done);
+                        }
+                    },
+                    event = swarm.select_next_some() => match event {
+                        // the first result of a query answers it, later steps are dropped
+                        SwarmEvent::Behaviour(BehaviourEvent::Kademlia(kad::Event::OutboundQueryProgressed { id, result, .. })) => {
+                            if let Some(done) = queries.remove(&id) {
+                                let _ = done.send(result);
+                            }
+                        }
+                        // peers that dialed us are routed to where they listen
+                        SwarmEvent::Behaviour(BehaviourEvent::Identify(identify::Event::Received { peer_id, info, .. })) => {
+                            for addr in info.listen_addrs {
+                                swarm.behaviour_mut().kademlia.add_address(&peer_id, addr);
+                            }
+                        }
+                        _ => {}
+                    },
+                }
+            }
+        });
+        self.tasks.insert(peer_id, task);
+
+        let peer = Peer { peer_id, addr, key, dir, actions };
+        for known in peers {
+            wait_for("the bootstrap peers to route to the new one", || known.routes_to(peer_id)).await;
+        }
+        peer
+    }
+}
+
+/// A swarm started by a test, driven through its Kademlia behaviour.
+struct Peer {
+    peer_id: PeerId,
+    addr: Multiaddr, // listen address with /p2p/<peer id>, what others bootstrap to
+    key: Keypair,
+    dir: PathBuf,
+    actions: mpsc::UnboundedSender<Action>,
+}
+
+impl Peer {
+    async fn call<T: Send + 'static>(&self, call: impl FnOnce(&mut Kademlia) -> T + Send + 'static) -> T {
+        let (reply, done) = oneshot::channel();
+        let call = Box::new(move |kademlia: &mut Kademlia| {
+            let _ = reply.send(call(kademlia));
+        });
+        self.actions.send(Action::Call(call)).expect("the peer is running");
+        time::timeout(TIMEOUT, done).await.expect("call in time").expect("the peer is running")
+    }
+
+    async fn query(&self, start: impl FnOnce(&mut Kademlia) -> QueryId + Send + 'static) -> QueryResult {
+        let (reply, done) = oneshot::channel();
+        self.actions.send(Action::Query(Box::new(start), reply)).expect("the peer is running");
+        time::timeout(TIMEOUT, done).await.expect("query in time").expect("the peer is running")
+    }
+
+    async fn put(&self, key: &str, value: &str, quorum: Quorum) -> Result<(), kad::PutRecordError> {
+        let record = Record::new(RecordKey::new(&key), value.as_bytes().to_vec());
+        match self.query(move |kademlia| kademlia.put_record(record, quorum).expect("store the record locally")).await {
+            QueryResult::PutRecord(result) => result.map(|_| ()),
+            other => panic!("expected a put result, got {other:?}"),
+        }
+    }
+
+    // the value of the first copy found, none if the lookup found nothing
+    async fn get(&self, key: &str) -> Option<String> {
+        let key = RecordKey::new(&key);
+        match self.query(move |kademlia| kademlia.get_record(key)).await {
+            QueryResult::GetRecord(Ok(kad::GetRecordOk::FoundRecord(found))) => {
+                Some(String::from_utf8(found.record.value).expect("test values are UTF-8"))
+            }
+            QueryResult::GetRecord(_) => None,
+            other => panic!("expected a get result, got {other:?}"),
+        }
+    }
+
+    async fn routes_to(&self, peer: PeerId) -> bool {
+        self.call(move |kademlia| {
+            kademlia.kbuckets().any(|bucket| bucket.iter().any(|entry| *entry.node.key.preimage() == peer))
+        })
+        .await
+    }
+}
+
+/// Polls `check` until it holds, panics after TIMEOUT.
+async fn wait_for<F: Future<Output = bool>>(what: &str, check: impl Fn() -> F) {
+    let waited = time::timeout(TIMEOUT, async {
+        while !check().await {
+            time::sleep(Duration::from_millis(50)).await;
+        }
+    });
+    waited.await.unwrap_or_else(|_| panic!("waited too long for {what}"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn records_are_found_through_the_dht() {
+    let mut net = Network::new();
+    let a = net.start(&[]).await;
+    let b = net.start(&[&a]).await;
+    // c only knows b, the record a put is found through it
+    let c = net.start(&[&b]).await;
+
+    a.put("greeting", "hello", Quorum::One).await.unwrap();
+    assert_eq!(c.get("greeting").await.as_deref(), Some("hello"));
+    assert_eq!(c.get("missing").await, None);
+    net.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn providers_are_found_through_the_dht() {
+    let mut net = Network::new();
+    let a = net.start(&[]).await;
+    let b = net.start(&[&a]).await;
+    let c = net.start(&[&b]).await;
+
+    let result = a.query(|kademlia| kademlia.start_providing(RecordKey::new(&"file")).unwrap()).await;
+    assert!(matches!(result, QueryResult::StartProviding(Ok(_))), "{result:?}");
+    let result = c.query(|kademlia| kademlia.get_providers(RecordKey::new(&"file"))).await;
+    match result {
+        QueryResult::GetProviders(Ok(kad::GetProvidersOk::FoundProviders { providers, .. })) => {
+            assert!(providers.contains(&a.peer_id), "{providers:?}");
+        }
+        other => panic!("expected providers, got {other:?}"),
+    }
+    net.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn provider_records_are_counted_per_peer() {
+    let mut net = Network::new();
+    let a = net.start(&[]).await;
+    let b = net.start(&[&a]).await;
+    for key in ["one", "two", "three"] {
+        let result = b.query(move |kademlia| kademlia.start_providing(RecordKey::new(&key)).unwrap()).await;
+        assert!(matches!(result, QueryResult::StartProviding(Ok(_))), "{result:?}");
+    }
+    // the provider records are sent without waiting for an answer
+    let b_id = b.peer_id;
+    wait_for("a to hold b's provider records", || async {
+        a.call(move |kademlia| kademlia.store_mut().held_for(&b_id)).await == 3
+    })
+    .await;
+
+    // announcing a key again doesn't count twice, and the count is rebuilt from disk
+    for key in ["one", "four"] {
+        let result = b.query(move |kademlia| kademlia.start_providing(RecordKey::new(&key)).unwrap()).await;
+        assert!(matches!(result, QueryResult::StartProviding(Ok(_))), "{result:?}");
+    }
+    wait_for("a to hold the new provider record", || async {
+        a.call(move |kademlia| kademlia.store_mut().held_for(&b_id)).await >= 4
+    })
+    .await;
+    time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(a.call(move |kademlia| kademlia.store_mut().held_for(&b_id)).await, 4);
+    let a = net.restart(a, &[]).await;
+    assert_eq!(a.call(move |kademlia| kademlia.store_mut().held_for(&b_id)).await, 4);
+    net.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn records_outlive_the_peer_that_put_them() {
+    let mut net = Network::new();
+    let a = net.start(&[]).await;
+    let b = net.start(&[&a]).await;
+    let c = net.start(&[&a]).await;
+    wait_for("a to route to b and c", || async { a.routes_to(b.peer_id).await && a.routes_to(c.peer_id).await }).await;
+
+    // both other peers hold a copy
+    a.put("greeting", "hello", Quorum::N(2.try_into().unwrap())).await.unwrap();
+    net.kill(a).await;
+    let d = net.start(&[&b]).await;
+    assert_eq!(d.get("greeting").await.as_deref(), Some("hello"));
+    net.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn puts_fail_without_peers_to_store_on() {
+    let mut net = Network::new();
+    let a = net.start(&[]).await;
+    let b = net.start(&[&a]).await;
+    net.kill(b).await;
+
+    let error = a.put("greeting", "hello", Quorum::One).await.unwrap_err();
+    assert!(matches!(error, kad::PutRecordError::QuorumFailed { .. }), "{error:?}");
+    // the local copy stays
+    assert_eq!(a.get("greeting").await.as_deref(), Some("hello"));
+    net.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn records_survive_a_restart() {
+    let mut net = Network::new();
+    let a = net.start(&[]).await;
+    let b = net.start(&[&a]).await;
+    b.put("greeting", "hello", Quorum::One).await.unwrap();
+    net.kill(b).await;
+
+    // a comes back from its database alone, c only knows it
+    let a = net.restart(a, &[]).await;
+    let c = net.start(&[&a]).await;
+    assert_eq!(c.get("greeting").await.as_deref(), Some("hello"));
+    net.stop().await;
+}
@@ -1,6 +1,5 @@
 use sha2::{Sha256, Sha512, Digest};
-use blake2::{Blake2b512, digest::Update};
-use hex;
+use blake2::Blake2b512;
 use std::marker::PhantomData;
 
 // define hasher
@@ -38,6 +37,7 @@ impl Hasher for Sha512Hasher {
 pub struct MerkleTree<H: Hasher> {
     pub leaves: Vec<Vec<u8>>,
     pub root: String,
+    levels: Vec<Vec<String>>,
     _hasher: PhantomData<H>,
 }
 
@@ -45,33 +45,23 @@ impl<H: Hasher> MerkleTree<H> {
     
     // create a new markletree from given leaves 
     pub fn new(leaves: Vec<Vec<u8>>) -> Self {
-        //make root form given leaves
-        let  root = Self::build_tree(&leaves);
-        // root.push('h');
+        //make every level form given leaves, the last one is the root
+        let levels = Self::build_tree(&leaves);
+        let root = levels[levels.len() - 1][0].clone();
         //return tree
-        MerkleTree { leaves, root, _hasher: PhantomData }
+        MerkleTree { leaves, root, levels, _hasher: PhantomData }
     }
 
-    // build tree and return root
-    fn build_tree(leaves: &Vec<Vec<u8>>) -> String  {
+    // build tree and return the hashes of every level, leaves first
+    fn build_tree(leaves: &[Vec<u8>]) -> Vec<Vec<String>>  {
         
         //make hash of each leaf
         let mut hashes: Vec<String> = leaves.iter().map(|leaf| H::hash(leaf)).collect();
-
-        
-        // print all leaves hash 
-        for (i, hash) in hashes.iter().enumerate() {
-            println!("Leaf {} : {:?}", i+1, hash);
-        }
-        println!("");
-        let mut level = 0;
-        println!("Level {} (Leaves): {:?}\n", level, hashes);
+        let mut levels = vec![hashes.clone()];
         
         //combaining current lavel's
         while hashes.len() > 1 {
-            level += 1;
             let mut new_hashes = vec![];
-            let mut level_structure = vec![];
 
             for chunk in hashes.chunks(2) {
                 let combined_hash = if chunk.len() == 2 {
@@ -80,26 +70,25 @@ impl<H: Hasher> MerkleTree<H> {
                     } else {
                         (chunk[1].clone(), chunk[0].clone())
                     };
-                    level_structure.push((left.clone(), right.clone()));
                     //concatinace hashes
                     H::hash(format!("{}{}", left, right).as_bytes())
                 } else {
-                    // level_structure.push((chunk[0].clone(), chunk[0].clone()));
-                    level_structure.push((chunk[0].clone(), "carry forword".to_string()));
                     // if odd then carry forword 
                     chunk[0].clone() 
                 };
                 
                 new_hashes.push(combined_hash);
             }
-            println!("Level {}: {:?}\n", level, level_structure);
+            levels.push(new_hashes.clone());
             hashes = new_hashes;
         }
         
+        levels
+    }
 
-        // println!("Merkle Root: {:?}", hashes[0]);
-        //signle reaming is root hash
-        hashes[0].clone()
+    // hashes of every level, leaves at 0 and the root last
+    pub fn levels(&self) -> &[Vec<String>] {
+        &self.levels
     }
 
     //return root
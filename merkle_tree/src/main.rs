@@ -38,14 +38,22 @@ fn main() {
         _ => println!("Invalid choice!"),
     }
 
-    fn process_merkle_tree<H: merkle_tree::Hasher>(merkle_tree: MerkleTree<H>, leaves: &Vec<Vec<u8>>) {
+    fn process_merkle_tree<H: merkle_tree::Hasher>(merkle_tree: MerkleTree<H>, leaves: &[Vec<u8>]) {
+        // print all leaves hash and every level above them
+        for (i, hash) in merkle_tree.levels()[0].iter().enumerate() {
+            println!("Leaf {} : {:?}", i+1, hash);
+        }
+        println!();
+        for (level, hashes) in merkle_tree.levels().iter().enumerate() {
+            println!("Level {}: {:?}\n", level, hashes);
+        }
         println!("\nMerkle Root: {}", merkle_tree.get_root());
     
-        for i in 0..leaves.len() {
+        for (i, leaf) in leaves.iter().enumerate() {
             let proof = merkle_tree.get_proof(i);
             println!("\nProof for LEAF {}: {:?}", i + 1, proof);
     
-            let is_valid = MerkleTree::<H>::verify_proof(leaves[i].clone(), proof, merkle_tree.get_root());
+            let is_valid = MerkleTree::<H>::verify_proof(leaf.clone(), proof, merkle_tree.get_root());
             println!("\nProof Verification: {}", is_valid);
         }
     }
@@ -0,0 +1,157 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use serde::{Deserialize, Serialize};
+
+// where copies of retrieved files are written unless --retrieved says otherwise
+pub const DEFAULT_RETRIEVED_DIR: &str = "retrieved";
+
+// size and last use of a stored file, LRU eviction goes by the last use
+#[derive(Serialize, Deserialize)]
+struct Usage {
+    size: u64,
+    last_used: u64, // unix ms
+}
+
+/// Pins, last use and retrieved copies of the files this node keeps, in their own sled trees.
+#[derive(Clone)]
+pub struct Cache {
+    pins: sled::Tree,      // key -> empty, pinned files are never evicted
+    usage: sled::Tree,     // key -> Usage
+    retrieved: sled::Tree, // path of a retrieved copy -> key it was written from
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+// byte count with an optional K, M or G suffix (powers of 1024)
+pub fn parse_size(text: &str) -> Option<u64> {
+    let text = text.trim();
+    let (number, unit) = match text.char_indices().last()? {
+        (i, c) if c.is_ascii_alphabetic() => (&text[..i], c.to_ascii_uppercase()),
+        _ => (text, 'B'),
+    };
+    let shift = match unit {
+        'B' => 0,
+        'K' => 10,
+        'M' => 20,
+        'G' => 30,
+        _ => return None,
+    };
+    number.trim().parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+impl Cache {
+    pub fn open(db: &sled::Db) -> sled::Result<Self> {
+        Ok(Cache {
+            pins: db.open_tree("pins")?,
+            usage: db.open_tree("usage")?,
+            retrieved: db.open_tree("retrieved")?,
+        })
+    }
+
+    fn usage(&self, key: &str) -> Option<Usage> {
+        bincode::deserialize(&self.usage.get(key).ok()??).ok()
+    }
+
+    fn set_usage(&self, key: &str, usage: &Usage) {
+        let bytes = bincode::serialize(usage).expect("usage serializes");
+        if let Err(e) = self.usage.insert(key, bytes) {
+            eprintln!("Failed to record use of {key}: {e}");
+        }
+    }
+
+    // a file was stored or replaced, it counts as used now
+    pub fn record(&self, key: &str, size: u64) {
+        self.set_usage(key, &Usage { size, last_used: now_ms() });
+    }
+
+    // a file stored before usage was tracked
+    pub fn adopt(&self, key: &str, size: u64) {
+        if self.usage(key).is_none() {
+            self.record(key, size);
+        }
+    }
+
+    // a file was read, locally or by a peer
+    pub fn touch(&self, key: &str) {
+        if let Some(mut usage) = self.usage(key) {
+            usage.last_used = now_ms();
+            self.set_usage(key, &usage);
+        }
+    }
+
+    // the file is gone, so are its pin and usage
+    pub fn forget(&self, key: &str) {
+        let _ = self.pins.remove(key);
+        let _ = self.usage.remove(key);
+    }
+
+    pub fn pin(&self, key: &str) -> sled::Result<()> {
+        self.pins.insert(key, &[])?;
+        Ok(())
+    }
+
+    // false if the file wasn't pinned
+    pub fn unpin(&self, key: &str) -> sled::Result<bool> {
+        Ok(self.pins.remove(key)?.is_some())
+    }
+
+    pub fn is_pinned(&self, key: &str) -> bool {
+        self.pins.contains_key(key).unwrap_or(false)
+    }
+
+    // unpinned files in the order they are evicted, least recently used first. `keep` is never
+    // picked, it was just stored. Files share chunks, so how much evicting one frees is only
+    // known once it's gone
+    pub fn eviction_order(&self, keep: Option<&str>) -> Vec<String> {
+        let mut candidates: Vec<(String, Usage)> = self
+            .usage
+            .iter()
+            .filter_map(|entry| {
+                let (key, bytes) = entry.ok()?;
+                let key = String::from_utf8_lossy(&key).to_string();
+                Some((key, bincode::deserialize(&bytes).ok()?))
+            })
+            .filter(|(key, _)| Some(key.as_str()) != keep && !self.is_pinned(key))
+            .collect();
+        candidates.sort_by_key(|(_, usage)| usage.last_used);
+        candidates.into_iter().map(|(key, _)| key).collect()
+    }
+
+    // drop pins and usage of files that are no longer stored, returns how many were dropped
+    pub fn retain(&self, stored: impl Fn(&str) -> bool) -> usize {
+        let mut dropped = 0;
+        for tree in [&self.pins, &self.usage] {
+            for key in tree.iter().keys().filter_map(|key| key.ok()) {
+                if !stored(&String::from_utf8_lossy(&key)) && tree.remove(&key).is_ok() {
+                    dropped += 1;
+                }
+            }
+        }
+        dropped
+    }
+
+    pub fn add_retrieved(&self, path: &Path, key: &str) {
+        if let Err(e) = self.retrieved.insert(path.to_string_lossy().as_bytes(), key.as_bytes()) {
+            eprintln!("Failed to record retrieved copy {}: {e}", path.display());
+        }
+    }
+
+    // retrieved copies of files that are no longer stored, or that were removed by hand.
+    // Their entries are dropped, deleting the copies is left to the caller
+    pub fn take_stale_retrieved(&self, stored: impl Fn(&str) -> bool) -> Vec<PathBuf> {
+        let mut stale = Vec::new();
+        for (path, key) in self.retrieved.iter().filter_map(|entry| entry.ok()) {
+            let path = PathBuf::from(String::from_utf8_lossy(&path).to_string());
+            if (!stored(&String::from_utf8_lossy(&key)) || !path.exists())
+                && self.retrieved.remove(path.to_string_lossy().as_bytes()).is_ok()
+            {
+                stale.push(path);
+            }
+        }
+        stale
+    }
+}
@@ -0,0 +1,167 @@
+use std::{
+    collections::HashSet,
+    io::{self, Read},
+};
+use fastcdc::v2020::StreamCDC;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::directory::Kind;
+
+// content-defined chunk bounds, a chunk is the unit of transfer and deduplication
+pub const MIN_CHUNK_SIZE: u32 = 64 * 1024;
+pub const AVG_CHUNK_SIZE: u32 = 256 * 1024;
+pub const MAX_CHUNK_SIZE: u32 = 1024 * 1024;
+
+// manifests before FastCDC used fixed 256 KiB chunks named by sha256
+const LEGACY_VERSION: u8 = 0;
+pub const MANIFEST_VERSION: u8 = 1;
+
+// stored and served under the file key, lists everything needed to rebuild the file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    #[serde(default)]
+    pub version: u8,
+    pub filename: String,
+    pub size: u64,
+    pub chunks: Vec<String>, // blake3 of each chunk, in file order
+    #[serde(default)]
+    pub lengths: Vec<u32>, // byte length of each chunk, lets a range read skip to its first chunk
+    #[serde(default)]
+    pub kind: Kind, // directory nodes are marked when stored, older manifests are all files
+}
+
+impl Manifest {
+    // chunk id as this manifest names it
+    pub fn chunk_id(&self, data: &[u8]) -> String {
+        match self.version {
+            LEGACY_VERSION => format!("{:x}", Sha256::digest(data)),
+            _ => hash_chunk(data),
+        }
+    }
+
+    // what a peer sends has to add up: one length per chunk, summing to the size, none over the
+    // chunk bound. Manifests from before lengths were recorded have none
+    pub fn is_valid(&self) -> bool {
+        if self.lengths.is_empty() {
+            return self.size <= self.chunks.len() as u64 * MAX_CHUNK_SIZE as u64;
+        }
+        self.lengths.len() == self.chunks.len()
+            && self.lengths.iter().all(|length| *length <= MAX_CHUNK_SIZE)
+            && self.lengths.iter().map(|length| *length as u64).sum::<u64>() == self.size
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("manifest serializes")
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        serde_json::from_slice(bytes).ok()
+    }
+}
+
+// keys are the sha256 of the content unless one was chosen, a chosen one can't look like a sha256
+pub fn is_content_hash(key: &str) -> bool {
+    key.len() == 64 && key.bytes().all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+pub fn hash_chunk(data: &[u8]) -> String {
+    blake3::hash(data).to_hex().to_string()
+}
+
+// split what `reader` yields at content-defined boundaries, so an edit only changes the chunks
+// around it. Every chunk goes to `store` as soon as it's cut, the file never has to fit in
+// memory. Returns the manifest and the sha256 of the whole content
+pub fn split<R: Read>(
+    filename: &str,
+    reader: R,
+    mut store: impl FnMut(&str, &[u8]) -> io::Result<()>,
+) -> io::Result<(Manifest, String)> {
+    let mut content_hash = Sha256::new();
+    let mut manifest = Manifest {
+        version: MANIFEST_VERSION,
+        filename: filename.to_string(),
+        size: 0,
+        chunks: Vec::new(),
+        lengths: Vec::new(),
+        kind: Kind::File,
+    };
+    for chunk in StreamCDC::new(reader, MIN_CHUNK_SIZE, AVG_CHUNK_SIZE, MAX_CHUNK_SIZE) {
+        let chunk = chunk?;
+        let hash = hash_chunk(&chunk.data);
+        store(&hash, &chunk.data)?;
+        content_hash.update(&chunk.data);
+        manifest.size += chunk.length as u64;
+        manifest.chunks.push(hash);
+        manifest.lengths.push(chunk.length as u32);
+    }
+    Ok((manifest, format!("{:x}", content_hash.finalize())))
+}
+
+// a file being fetched chunk by chunk, the chunks themselves go straight to sled
+pub struct Download {
+    pub manifest: Manifest,
+    received: Vec<bool>,
+    bytes: u64, // content bytes of the received chunks
+}
+
+impl Download {
+    pub fn new(manifest: Manifest) -> Self {
+        let received = vec![false; manifest.chunks.len()];
+        Download { manifest, received, bytes: 0 }
+    }
+
+    // count the chunks already stored as received, `stored` returns their length. They were
+    // verified when written, by an earlier run of this download or for another file
+    pub fn mark_stored(&mut self, stored: impl Fn(&str) -> Option<u64>) {
+        for (i, hash) in self.manifest.chunks.iter().enumerate() {
+            if !self.received[i]
+                && let Some(length) = stored(hash)
+            {
+                self.received[i] = true;
+                self.bytes += length;
+            }
+        }
+    }
+
+    // hashes still to fetch, each once
+    pub fn missing(&self) -> Vec<String> {
+        let mut seen = HashSet::new();
+        self.manifest
+            .chunks
+            .iter()
+            .zip(&self.received)
+            .filter(|(hash, received)| !**received && seen.insert(*hash))
+            .map(|(hash, _)| hash.clone())
+            .collect()
+    }
+
+    // check a received chunk, returns false if it doesn't belong to this file or fails the hash check
+    pub fn insert(&mut self, hash: &str, data: &[u8]) -> bool {
+        if self.manifest.chunk_id(data) != hash {
+            return false;
+        }
+        let mut matched = false;
+        // the same chunk can appear more than once in a file
+        for (i, expected) in self.manifest.chunks.iter().enumerate() {
+            if expected == hash && !self.received[i] {
+                self.received[i] = true;
+                self.bytes += data.len() as u64;
+                matched = true;
+            }
+        }
+        matched
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.received.iter().all(|received| *received)
+    }
+
+    pub fn received(&self) -> usize {
+        self.received.iter().filter(|received| **received).count()
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+}
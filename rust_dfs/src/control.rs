@@ -0,0 +1,257 @@
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use std::{
+    error::Error,
+    fs, io,
+    os::unix::fs::{DirBuilderExt, PermissionsExt},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::{mpsc, oneshot};
+#[cfg(unix)]
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+};
+
+use crate::gateway::Command;
+
+// JSON-RPC 2.0 error codes, CALL_FAILED is ours: the method ran and the message says why it failed
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const CALL_FAILED: i64 = -32000;
+
+pub const METHODS: &[&str] = &["put", "get", "list", "pin", "unpin", "peers", "status"];
+// where `rust_dfs ctl` connects unless given --socket, next to the default database
+#[cfg(unix)]
+const DEFAULT_SOCKET: &str = "file_store/db.sock";
+
+/// A control API method with its parameters, as the swarm loop runs it.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "lowercase")]
+pub enum Call {
+    // a file or directory on the node's disk, encrypted with a passphrase or to a public key
+    Put {
+        path: PathBuf,
+        key: Option<String>,
+        passphrase: Option<String>,
+        to: Option<String>,
+    },
+    // a key or dfs1: capability. Answered once the file is written to the retrieved directory
+    Get {
+        key: String,
+        passphrase: Option<String>,
+    },
+    List,
+    Pin {
+        key: String,
+    },
+    Unpin {
+        key: String,
+    },
+    Peers,
+    Status,
+}
+
+// the result of a call, or why it failed
+pub type Reply = oneshot::Sender<Result<Value, String>>;
+
+/// Which files `put` may read. The control socket is the node's owner, `POST /rpc` only gets
+/// the directory given with --rpc-put-dir.
+#[derive(Debug, Clone)]
+pub enum PutAccess {
+    Anywhere,
+    Below(PathBuf),
+    Nowhere,
+}
+
+impl PutAccess {
+    // the path put reads, resolved so a symlink can't lead out of the allowed directory
+    fn check(&self, path: &Path) -> Result<PathBuf, String> {
+        let dir = match self {
+            PutAccess::Anywhere => return Ok(path.to_path_buf()),
+            PutAccess::Nowhere => return Err("put isn't allowed over HTTP, start the node with --rpc-put-dir <dir>".to_string()),
+            PutAccess::Below(dir) => dir,
+        };
+        let dir = dir.canonicalize().map_err(|e| format!("Can't read {}: {e}", dir.display()))?;
+        let resolved = path.canonicalize().map_err(|_| format!("File does not exist: {}", path.display()))?;
+        if !resolved.starts_with(&dir) {
+            return Err(format!("put over HTTP only reads below {}", dir.display()));
+        }
+        Ok(resolved)
+    }
+}
+
+#[derive(Deserialize)]
+struct Request {
+    jsonrpc: String,
+    id: Option<Value>, // none for notifications, which get no response
+    method: String,
+    params: Option<Value>,
+}
+
+#[derive(Serialize)]
+struct Response {
+    jsonrpc: &'static str,
+    id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<Fault>,
+}
+
+#[derive(Serialize)]
+struct Fault {
+    code: i64,
+    message: String,
+}
+
+fn respond(id: Value, outcome: Result<Value, (i64, String)>) -> String {
+    let (result, error) = match outcome {
+        Ok(result) => (Some(result), None),
+        Err((code, message)) => (None, Some(Fault { code, message })),
+    };
+    serde_json::to_string(&Response { jsonrpc: "2.0", id, result, error }).expect("response serializes")
+}
+
+/// Runs one JSON-RPC request and returns the response, none for a notification.
+pub async fn handle(text: &str, commands: &mpsc::UnboundedSender<Command>, access: &PutAccess) -> Option<String> {
+    let request = match serde_json::from_str::<Value>(text) {
+        Ok(value) => serde_json::from_value::<Request>(value),
+        Err(e) => return Some(respond(Value::Null, Err((PARSE_ERROR, e.to_string())))),
+    };
+    let request = match request {
+        Ok(request) => request,
+        Err(e) => return Some(respond(Value::Null, Err((INVALID_REQUEST, e.to_string())))),
+    };
+    let id = request.id.clone();
+    let outcome = call(request, commands, access).await;
+    Some(respond(id?, outcome))
+}
+
+async fn call(request: Request, commands: &mpsc::UnboundedSender<Command>, access: &PutAccess) -> Result<Value, (i64, String)> {
+    if request.jsonrpc != "2.0" {
+        return Err((INVALID_REQUEST, "jsonrpc must be \"2.0\"".to_string()));
+    }
+    if !METHODS.contains(&request.method.as_str()) {
+        return Err((METHOD_NOT_FOUND, format!("unknown method {}, use one of {}", request.method, METHODS.join(", "))));
+    }
+    // methods without parameters take none, an empty object or null
+    let mut tagged = json!({ "method": request.method });
+    if let Some(params) = request.params.filter(|params| !params.is_null() && *params != json!({})) {
+        tagged["params"] = params;
+    }
+    let mut call = serde_json::from_value(tagged).map_err(|e| (INVALID_PARAMS, e.to_string()))?;
+    if let Call::Put { path, .. } = &mut call {
+        *path = access.check(path).map_err(|e| (INVALID_PARAMS, e))?;
+    }
+
+    let (reply, done) = oneshot::channel();
+    let shutting_down = || (CALL_FAILED, "node is shutting down".to_string());
+    commands.send(Command::Call { call, reply }).map_err(|_| shutting_down())?;
+    match done.await {
+        Ok(Ok(result)) => Ok(result),
+        Ok(Err(reason)) => Err((CALL_FAILED, reason)),
+        Err(_) => Err(shutting_down()),
+    }
+}
+
+/// Binds the control socket and returns the server future. Requests and responses are
+/// JSON-RPC, one per line.
+#[cfg(unix)]
+pub fn bind(path: &Path, commands: mpsc::UnboundedSender<Command>) -> io::Result<impl Future<Output = ()> + use<>> {
+    // a socket left by a node that didn't shut down cleanly is replaced, a live one isn't
+    if path.exists() {
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, "another node is listening on it"));
+        }
+        fs::remove_file(path)?;
+    }
+    // whoever can connect controls the node. The socket is bound in a directory only its owner
+    // can enter and made private there, so nobody can connect before it's moved into place
+    let name = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file path"))?;
+    let staging = path.with_file_name(format!(".{}.bind", name.to_string_lossy()));
+    let _ = fs::remove_dir_all(&staging);
+    fs::DirBuilder::new().mode(0o700).create(&staging)?;
+    let bound = (|| {
+        let private = staging.join("socket");
+        let listener = UnixListener::bind(&private)?;
+        fs::set_permissions(&private, fs::Permissions::from_mode(0o600))?;
+        fs::rename(&private, path)?;
+        Ok::<_, io::Error>(listener)
+    })();
+    let _ = fs::remove_dir_all(&staging);
+    let listener = bound?;
+    Ok(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(serve(stream, commands.clone()));
+                }
+                Err(e) => eprintln!("Control socket failed to accept a connection: {e}"),
+            }
+        }
+    })
+}
+
+// requests of one connection are answered in order, a `get` holds up the ones after it
+#[cfg(unix)]
+async fn serve(stream: UnixStream, commands: mpsc::UnboundedSender<Command>) {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = handle(&line, &commands, &PutAccess::Anywhere).await
+            && write.write_all(format!("{response}\n").as_bytes()).await.is_err()
+        {
+            break;
+        }
+    }
+}
+
+/// `rust_dfs ctl [--socket <path>] <method> [name=value]...`, a client of the control socket.
+/// Prints the result as JSON.
+#[cfg(unix)]
+pub async fn run(args: impl IntoIterator<Item = String>) -> Result<(), Box<dyn Error>> {
+    let mut socket = PathBuf::from(DEFAULT_SOCKET);
+    let mut method = None;
+    let mut params = serde_json::Map::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--socket" => socket = args.next().ok_or("--socket needs a path")?.into(),
+            _ if method.is_none() => method = Some(arg),
+            _ => {
+                let (name, value) = arg.split_once('=').ok_or(format!("expected name=value, got {arg}"))?;
+                params.insert(name.to_string(), Value::String(value.to_string()));
+            }
+        }
+    }
+    let method = method.ok_or(format!("Usage: rust_dfs ctl [--socket <path>] <{}> [name=value]...", METHODS.join("|")))?;
+    // the node may run in another directory, paths are taken from where ctl runs
+    if let Some(Value::String(path)) = params.get_mut("path") {
+        *path = std::path::absolute(&*path)?.to_string_lossy().to_string();
+    }
+
+    let stream = UnixStream::connect(&socket)
+        .await
+        .map_err(|e| format!("Failed to connect to {}: {e}. Is the node running?", socket.display()))?;
+    let (read, mut write) = stream.into_split();
+    let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+    write.write_all(format!("{request}\n").as_bytes()).await?;
+    let line = BufReader::new(read)
+        .lines()
+        .next_line()
+        .await?
+        .ok_or("the node closed the connection")?;
+    let response: Value = serde_json::from_str(&line)?;
+    if let Some(error) = response.get("error") {
+        return Err(format!("{} ({})", error["message"].as_str().unwrap_or("unknown error"), error["code"]).into());
+    }
+    println!("{}", serde_json::to_string_pretty(&response["result"])?);
+    Ok(())
+}
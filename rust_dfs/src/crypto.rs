@@ -0,0 +1,353 @@
+use std::{
+    fmt,
+    io::{self, Cursor, Read, Write},
+};
+use argon2::Argon2;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use hkdf::Hkdf;
+use rand::{rngs::OsRng, RngCore};
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+// encrypted content starts with this, followed by the rest of the header
+const MAGIC: &[u8; 4] = b"DFSE";
+const VERSION: u8 = 1;
+// plaintext bytes per authenticated segment
+const SEGMENT_SIZE: usize = 64 * 1024;
+const TAG_SIZE: usize = 16;
+// 24 byte nonce = prefix, u32 segment counter, last-segment flag
+const NONCE_PREFIX_SIZE: usize = 19;
+const SALT_SIZE: usize = 16;
+
+pub type FileKey = [u8; 32];
+
+/// How a file gets its key when it's put.
+pub enum Lock {
+    Passphrase(String),
+    Recipient(PublicKey),
+}
+
+/// What a reader has to open a file.
+pub enum Unlock {
+    Key(FileKey),
+    Passphrase(String),
+    Secret(StaticSecret),
+}
+
+// how the file key was derived, kept in the clear in the header
+enum Derivation {
+    Passphrase { salt: [u8; SALT_SIZE] },
+    Recipient { ephemeral: [u8; 32], recipient: [u8; 32] },
+}
+
+struct Header {
+    derivation: Derivation,
+    nonce_prefix: [u8; NONCE_PREFIX_SIZE],
+}
+
+impl Header {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        match &self.derivation {
+            Derivation::Passphrase { salt } => {
+                bytes.push(0);
+                bytes.extend_from_slice(salt);
+            }
+            Derivation::Recipient { ephemeral, recipient } => {
+                bytes.push(1);
+                bytes.extend_from_slice(ephemeral);
+                bytes.extend_from_slice(recipient);
+            }
+        }
+        bytes.extend_from_slice(&self.nonce_prefix);
+        bytes
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let rest = bytes.strip_prefix(MAGIC).ok_or("not an encrypted file")?;
+        let (version, rest) = rest.split_first().ok_or("truncated header")?;
+        if *version != VERSION {
+            return Err(format!("unsupported encryption version {version}"));
+        }
+        let (kind, rest) = rest.split_first().ok_or("truncated header")?;
+        let (derivation, rest) = match kind {
+            0 if rest.len() >= SALT_SIZE => {
+                let (salt, rest) = rest.split_at(SALT_SIZE);
+                (Derivation::Passphrase { salt: salt.try_into().unwrap() }, rest)
+            }
+            1 if rest.len() >= 64 => {
+                let (ephemeral, rest) = rest.split_at(32);
+                let (recipient, rest) = rest.split_at(32);
+                let derivation = Derivation::Recipient {
+                    ephemeral: ephemeral.try_into().unwrap(),
+                    recipient: recipient.try_into().unwrap(),
+                };
+                (derivation, rest)
+            }
+            _ => return Err("bad header".to_string()),
+        };
+        let nonce_prefix = rest.get(..NONCE_PREFIX_SIZE).ok_or("truncated header")?.try_into().unwrap();
+        Ok(Header { derivation, nonce_prefix })
+    }
+
+    // read the header off the front of an encrypted stream, returns it with its bytes
+    fn read(reader: &mut impl Read) -> Result<(Self, Vec<u8>), String> {
+        let mut bytes = read_up_to(reader, MAGIC.len() + 2).map_err(|e| e.to_string())?;
+        let rest = match bytes.last() {
+            Some(0) => SALT_SIZE,
+            Some(1) => 64,
+            _ => return Err("not an encrypted file".to_string()),
+        };
+        bytes.extend(read_up_to(reader, rest + NONCE_PREFIX_SIZE).map_err(|e| e.to_string())?);
+        Ok((Header::parse(&bytes)?, bytes))
+    }
+}
+
+// fewer bytes only at the end of the stream
+fn read_up_to(reader: &mut impl Read, len: usize) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(len);
+    reader.take(len as u64).read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn passphrase_key(passphrase: &str, salt: &[u8]) -> Result<FileKey, String> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| format!("key derivation failed: {e}"))?;
+    Ok(key)
+}
+
+fn recipient_key(shared: &[u8; 32], ephemeral: &[u8; 32], recipient: &[u8; 32]) -> FileKey {
+    let mut salt = ephemeral.to_vec();
+    salt.extend_from_slice(recipient);
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(&salt), shared)
+        .expand(b"rust_dfs file key", &mut key)
+        .expect("32 bytes is a valid hkdf output length");
+    key
+}
+
+fn nonce(prefix: &[u8; NONCE_PREFIX_SIZE], counter: u32, last: bool) -> XNonce {
+    let mut nonce = [0u8; 24];
+    nonce[..NONCE_PREFIX_SIZE].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_SIZE..23].copy_from_slice(&counter.to_be_bytes());
+    nonce[23] = last as u8;
+    XNonce::from(nonce)
+}
+
+pub fn is_encrypted(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Encrypts the file name and content while it is read, one segment at a time.
+pub struct Encryptor<R> {
+    plaintext: io::Chain<Cursor<Vec<u8>>, R>, // name length, name, then the content
+    cipher: XChaCha20Poly1305,
+    header: Vec<u8>,
+    nonce_prefix: [u8; NONCE_PREFIX_SIZE],
+    counter: u32,
+    ahead: Option<Vec<u8>>, // next plaintext segment, read early to know which one is last
+    out: Vec<u8>,
+    pos: usize,
+    done: bool,
+    key: FileKey,
+}
+
+impl<R: Read> Encryptor<R> {
+    pub fn new(name: &str, content: R, lock: &Lock) -> Result<Self, String> {
+        let (derivation, key) = match lock {
+            Lock::Passphrase(passphrase) => {
+                let mut salt = [0u8; SALT_SIZE];
+                OsRng.fill_bytes(&mut salt);
+                (Derivation::Passphrase { salt }, passphrase_key(passphrase, &salt)?)
+            }
+            Lock::Recipient(recipient) => {
+                let ephemeral = StaticSecret::random_from_rng(OsRng);
+                let shared = ephemeral.diffie_hellman(recipient);
+                let ephemeral = PublicKey::from(&ephemeral).to_bytes();
+                let key = recipient_key(shared.as_bytes(), &ephemeral, recipient.as_bytes());
+                (Derivation::Recipient { ephemeral, recipient: recipient.to_bytes() }, key)
+            }
+        };
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_SIZE];
+        OsRng.fill_bytes(&mut nonce_prefix);
+        let header = Header { derivation, nonce_prefix }.to_bytes();
+
+        // the name is encrypted with the content so nodes don't learn it
+        let name_len = u16::try_from(name.len()).map_err(|_| "file name too long")?;
+        let mut prefix = name_len.to_be_bytes().to_vec();
+        prefix.extend_from_slice(name.as_bytes());
+
+        Ok(Encryptor {
+            plaintext: Cursor::new(prefix).chain(content),
+            cipher: XChaCha20Poly1305::new(&key.into()),
+            out: header.clone(),
+            header,
+            nonce_prefix,
+            counter: 0,
+            ahead: None,
+            pos: 0,
+            done: false,
+            key,
+        })
+    }
+
+    // goes into the capability
+    pub fn key(&self) -> FileKey {
+        self.key
+    }
+
+    fn seal_next(&mut self) -> io::Result<()> {
+        let segment = match self.ahead.take() {
+            Some(segment) => segment,
+            None => read_up_to(&mut self.plaintext, SEGMENT_SIZE)?,
+        };
+        let next = read_up_to(&mut self.plaintext, SEGMENT_SIZE)?;
+        let last = next.is_empty();
+        // the header is authenticated with every segment
+        let payload = Payload { msg: &segment, aad: &self.header };
+        self.out = self
+            .cipher
+            .encrypt(&nonce(&self.nonce_prefix, self.counter, last), payload)
+            .map_err(|_| io::Error::other("encryption failed"))?;
+        self.pos = 0;
+        self.counter += 1;
+        if last {
+            self.done = true;
+        } else {
+            self.ahead = Some(next);
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Encryptor<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.out.len() {
+            if self.done {
+                return Ok(0);
+            }
+            self.seal_next()?;
+        }
+        let n = buf.len().min(self.out.len() - self.pos);
+        buf[..n].copy_from_slice(&self.out[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+fn file_key(unlock: &Unlock, header: &Header) -> Result<FileKey, String> {
+    match (unlock, &header.derivation) {
+        (Unlock::Key(key), _) => Ok(*key),
+        (Unlock::Passphrase(passphrase), Derivation::Passphrase { salt }) => passphrase_key(passphrase, salt),
+        (Unlock::Secret(secret), Derivation::Recipient { ephemeral, recipient }) => {
+            if PublicKey::from(secret).as_bytes() != recipient {
+                return Err("file was encrypted for another key".to_string());
+            }
+            let shared = secret.diffie_hellman(&PublicKey::from(*ephemeral));
+            Ok(recipient_key(shared.as_bytes(), ephemeral, recipient))
+        }
+        (Unlock::Passphrase(_), _) => Err("file was not encrypted with a passphrase".to_string()),
+        (Unlock::Secret(_), _) => Err("file was not encrypted for a recipient key".to_string()),
+    }
+}
+
+/// Checks and decrypts every segment as it is read. `open` gets the original file name and
+/// returns where the content goes, it isn't called unless the key is right. Returns the name.
+pub fn decrypt_stream<R: Read, W: Write>(
+    mut reader: R,
+    unlock: &Unlock,
+    open: impl FnOnce(&str) -> io::Result<W>,
+) -> Result<String, String> {
+    let (header, aad) = Header::read(&mut reader)?;
+    let cipher = XChaCha20Poly1305::new(&file_key(unlock, &header)?.into());
+
+    let mut open = Some(open);
+    let mut out: Option<(String, W)> = None;
+    let mut start = Vec::new(); // plaintext until the whole name is in
+    let mut counter = 0;
+    let mut segment = read_up_to(&mut reader, SEGMENT_SIZE + TAG_SIZE).map_err(|e| e.to_string())?;
+    loop {
+        let next = read_up_to(&mut reader, SEGMENT_SIZE + TAG_SIZE).map_err(|e| e.to_string())?;
+        let last = next.is_empty();
+        let payload = Payload { msg: &segment, aad: &aad };
+        let opened = cipher
+            .decrypt(&nonce(&header.nonce_prefix, counter, last), payload)
+            .map_err(|_| "wrong key or corrupted content")?;
+
+        match out.as_mut() {
+            Some((_, writer)) => writer.write_all(&opened).map_err(|e| e.to_string())?,
+            None => {
+                start.extend(opened);
+                if start.len() >= 2 {
+                    let name_len = u16::from_be_bytes([start[0], start[1]]) as usize;
+                    if start.len() >= 2 + name_len {
+                        let name = String::from_utf8_lossy(&start[2..2 + name_len]).to_string();
+                        let open = open.take().expect("opened once");
+                        let mut writer = open(&name).map_err(|e| e.to_string())?;
+                        writer.write_all(&start[2 + name_len..]).map_err(|e| e.to_string())?;
+                        out = Some((name, writer));
+                    }
+                }
+            }
+        }
+        if last {
+            break;
+        }
+        segment = next;
+        counter += 1;
+    }
+    let (name, mut writer) = out.ok_or("missing file name")?;
+    writer.flush().map_err(|e| e.to_string())?;
+    Ok(name)
+}
+
+/// File key plus decryption key, what someone needs to fetch and read an encrypted file.
+pub struct Capability {
+    pub key: String,
+    pub file_key: FileKey,
+}
+
+impl Capability {
+    // dfs1:<file key in the DHT>:<base64url decryption key>
+    pub fn parse(text: &str) -> Option<Self> {
+        let rest = text.strip_prefix("dfs1:")?;
+        let (key, secret) = rest.rsplit_once(':')?;
+        let file_key = URL_SAFE_NO_PAD.decode(secret).ok()?.try_into().ok()?;
+        Some(Capability { key: key.to_string(), file_key })
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "dfs1:{}:{}", self.key, URL_SAFE_NO_PAD.encode(self.file_key))
+    }
+}
+
+pub fn encode_public(key: &PublicKey) -> String {
+    URL_SAFE_NO_PAD.encode(key.as_bytes())
+}
+
+pub fn parse_public(text: &str) -> Option<PublicKey> {
+    let bytes: [u8; 32] = URL_SAFE_NO_PAD.decode(text).ok()?.try_into().ok()?;
+    Some(PublicKey::from(bytes))
+}
+
+// this node's recipient key, created on first use and kept in sled
+pub fn node_secret(db: &sled::Db) -> sled::Result<StaticSecret> {
+    let keys = db.open_tree("keys")?;
+    if let Some(bytes) = keys.get("x25519")?
+        && let Ok(bytes) = <[u8; 32]>::try_from(bytes.as_ref())
+    {
+        return Ok(StaticSecret::from(bytes));
+    }
+    let secret = StaticSecret::random_from_rng(OsRng);
+    keys.insert("x25519", secret.to_bytes().as_slice())?;
+    Ok(secret)
+}
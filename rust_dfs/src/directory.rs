@@ -0,0 +1,60 @@
+use serde::{Deserialize, Serialize};
+
+// a directory node is stored like any file, its content starts with this. What a stored file
+// is comes from its manifest, a file that happens to start the same way stays a file
+const MAGIC: &[u8; 4] = b"DFSD";
+const VERSION: u8 = 1;
+// what the gateway and LIST_FILE show for directory nodes
+pub const MIME: &str = "inode/directory";
+// deepest nesting written back to disk
+pub const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    #[default]
+    File,
+    Directory,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub name: String,
+    pub kind: Kind,
+    pub key: String, // key the file or directory node is stored under
+    pub size: u64,   // bytes of the file, or of every file below the directory
+}
+
+/// One node of a directory DAG. Entries name their children by the hash of their content,
+/// which downloads check, so a root stored under its own hash covers the whole tree.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Directory {
+    pub name: String,
+    pub entries: Vec<Entry>, // sorted by name, the same tree always hashes the same
+}
+
+impl Directory {
+    pub fn new(name: &str, mut entries: Vec<Entry>) -> Self {
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Directory { name: name.to_string(), entries }
+    }
+
+    // bytes of every file below this directory
+    pub fn size(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.size).sum()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bytes.extend(serde_json::to_vec(self).expect("directory serializes"));
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        match bytes.strip_prefix(MAGIC)?.split_first()? {
+            (&VERSION, body) => serde_json::from_slice(body).ok(),
+            _ => None,
+        }
+    }
+}
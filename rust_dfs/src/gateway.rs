@@ -0,0 +1,646 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    io::Read,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use futures::{StreamExt, TryStreamExt};
+use serde::Serialize;
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{mpsc, oneshot},
+};
+use warp::{
+    http::{Response, StatusCode},
+    hyper::{body::Buf, Body},
+    multipart::{FormData, Part},
+    Filter,
+};
+
+use crate::cache::Cache;
+use crate::chunk::Manifest;
+use crate::control::{self, Call, PutAccess};
+use crate::directory::{Directory, Kind};
+use crate::peers::PeerInfo;
+use crate::storage::{ChunkReader, Storage};
+use crate::stored_file::StoredFile;
+use crate::transfer::Progress;
+
+// largest upload accepted by POST /file, it is spooled to a temporary file rather than memory
+const MAX_UPLOAD_BYTES: u64 = 64 * 1024 * 1024 * 1024;
+// largest JSON-RPC request accepted by POST /rpc
+const MAX_RPC_BYTES: u64 = 1024 * 1024;
+// how long a GET waits for a file to come in from the DHT
+const FETCH_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Requests the HTTP side sends to the swarm loop, which owns the DHT.
+pub enum Command {
+    Put {
+        key: Option<String>,
+        name: String,
+        path: PathBuf, // the uploaded content, read from disk while it's chunked
+        reply: oneshot::Sender<Result<String, String>>,
+    },
+    Fetch {
+        key: String,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    Delete {
+        key: String,
+        reply: oneshot::Sender<bool>,
+    },
+    Progress {
+        reply: oneshot::Sender<Vec<Progress>>,
+    },
+    Cancel {
+        key: String,
+        reply: oneshot::Sender<bool>,
+    },
+    Peers {
+        reply: oneshot::Sender<Vec<PeerInfo>>,
+    },
+    Metrics {
+        reply: oneshot::Sender<String>, // Prometheus text format
+    },
+    // a control API method, from POST /rpc or the control socket
+    Call {
+        call: Call,
+        reply: control::Reply,
+    },
+}
+
+// HTTP requests waiting for a download, by file key
+pub type Waiters = HashMap<String, Vec<oneshot::Sender<Result<(), String>>>>;
+
+pub fn notify(waiters: &mut Waiters, key: &str, result: Result<(), String>) {
+    for reply in waiters.remove(key).unwrap_or_default() {
+        let _ = reply.send(result.clone());
+    }
+}
+
+// everything about a file except its content, for GET /files and POST /file
+#[derive(Serialize)]
+struct FileInfo {
+    key: String,
+    name: String,
+    size: u64,
+    mime: String,
+    hash: String,
+}
+
+impl FileInfo {
+    fn new(key: &str, file: &StoredFile) -> Self {
+        FileInfo {
+            key: key.to_string(),
+            name: file.name.clone(),
+            size: file.size,
+            mime: file.mime.clone(),
+            hash: file.hash.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Gateway {
+    db: sled::Db,
+    storage: Storage,
+    cache: Cache,
+    commands: mpsc::UnboundedSender<Command>,
+    rpc: Option<PutAccess>, // None unless the node runs with --http-rpc
+}
+
+impl Gateway {
+    // sled reads block, they run on the blocking pool rather than the server's worker threads
+    async fn blocking<T: Send + 'static>(&self, read: impl FnOnce(&Gateway) -> T + Send + 'static) -> Result<T, String> {
+        let gateway = self.clone();
+        tokio::task::spawn_blocking(move || read(&gateway))
+            .await
+            .map_err(|e| format!("Failed to read from sled: {e}"))
+    }
+
+    // a local copy is being used, it moves to the back of the eviction order
+    async fn local(&self, key: &str) -> Result<Option<(StoredFile, Manifest)>, String> {
+        let key = key.to_string();
+        self.blocking(move |gateway| {
+            let found = (StoredFile::load(&gateway.db, &key)?, gateway.storage.manifest(&key)?);
+            gateway.cache.touch(&key);
+            Some(found)
+        })
+        .await
+    }
+
+    // local copy first, otherwise ask the swarm to pull it from its providers
+    async fn find(&self, key: &str) -> Result<(StoredFile, Manifest), String> {
+        if let Some(found) = self.local(key).await? {
+            return Ok(found);
+        }
+        let (reply, done) = oneshot::channel();
+        self.commands
+            .send(Command::Fetch { key: key.to_string(), reply })
+            .map_err(|_| "node is shutting down".to_string())?;
+        match tokio::time::timeout(FETCH_TIMEOUT, done).await {
+            Ok(Ok(Ok(()))) => self.local(key).await?.ok_or_else(|| "file vanished after download".to_string()),
+            Ok(Ok(Err(reason))) => Err(reason),
+            Ok(Err(_)) => Err("node is shutting down".to_string()),
+            Err(_) => Err("timed out waiting for the DHT".to_string()),
+        }
+    }
+}
+
+// a header made from a stored name can still be refused by the builder, answer 500 rather than panic
+fn built(response: Result<Response<Body>, warp::http::Error>) -> Response<Body> {
+    response.unwrap_or_else(|e| {
+        let mut response = Response::new(Body::from(format!("Failed to build the response: {e}")));
+        *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+        response
+    })
+}
+
+fn text(status: StatusCode, body: impl Into<String>) -> Response<Body> {
+    built(
+        Response::builder()
+            .status(status)
+            .header("Content-Type", "text/plain")
+            .body(Body::from(body.into())),
+    )
+}
+
+fn json(status: StatusCode, value: &impl Serialize) -> Response<Body> {
+    built(
+        Response::builder()
+            .status(status)
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::to_vec(value).expect("json serializes"))),
+    )
+}
+
+fn empty(status: StatusCode) -> Response<Body> {
+    built(Response::builder().status(status).body(Body::empty()))
+}
+
+// single `bytes=` range as (start, end inclusive). Some(None) if it can't be satisfied,
+// None if the header isn't something we handle, the whole file is sent then
+fn parse_range(header: &str, size: u64) -> Option<Option<(u64, u64)>> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let range = match (start.trim(), end.trim()) {
+        ("", "") => return None,
+        ("", suffix) => {
+            let len: u64 = suffix.parse().ok()?;
+            (len > 0 && size > 0).then(|| (size.saturating_sub(len), size - 1))
+        }
+        (start, "") => {
+            let start: u64 = start.parse().ok()?;
+            (start < size).then(|| (start, size - 1))
+        }
+        (start, end) => {
+            let (start, end): (u64, u64) = (start.parse().ok()?, end.parse().ok()?);
+            (start <= end && start < size).then(|| (start, end.min(size - 1)))
+        }
+    };
+    Some(range)
+}
+
+// names come from uploads and other peers' manifests. The quoted filename keeps printable ASCII
+// other than quotes and backslashes, `filename*` carries the real name percent-encoded (RFC 6266)
+fn content_disposition(name: &str) -> String {
+    let fallback: String = name
+        .chars()
+        .map(|c| if (c.is_ascii_graphic() || c == ' ') && c != '"' && c != '\\' { c } else { '_' })
+        .collect();
+    let mut encoded = String::new();
+    for byte in name.bytes() {
+        if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    format!("attachment; filename=\"{fallback}\"; filename*=UTF-8''{encoded}")
+}
+
+// headers shared by GET and HEAD
+fn file_headers(file: &StoredFile) -> warp::http::response::Builder {
+    Response::builder()
+        .header("Content-Type", &file.mime)
+        .header("Content-Disposition", content_disposition(&file.name))
+        .header("Accept-Ranges", "bytes")
+        .header("ETag", format!("\"{}\"", file.hash))
+}
+
+// the content of a file, each chunk read from sled on the blocking pool as the client takes it
+fn body(reader: ChunkReader) -> Body {
+    let chunks = futures::stream::unfold(reader, |mut reader| async move {
+        // a failed read ends the body short of its Content-Length, the client sees it broken off
+        let (next, reader) = tokio::task::spawn_blocking(move || (reader.next(), reader)).await.ok()?;
+        Some((next?, reader))
+    });
+    Body::wrap_stream(chunks)
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// entries of a directory node, as JSON when the client asks for it and as a page of links otherwise
+fn directory_listing(directory: &Directory, wants_json: bool) -> Response<Body> {
+    if wants_json {
+        return json(StatusCode::OK, directory);
+    }
+    let name = escape_html(&directory.name);
+    let mut page = format!("<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{name}/</title></head><body>\n<h1>{name}/</h1>\n<ul>\n");
+    for entry in &directory.entries {
+        let slash = if entry.kind == Kind::Directory { "/" } else { "" };
+        page.push_str(&format!(
+            "<li><a href=\"/file/{}\">{}{slash}</a> ({} bytes)</li>\n",
+            escape_html(&entry.key),
+            escape_html(&entry.name),
+            entry.size
+        ));
+    }
+    page.push_str("</ul>\n</body></html>\n");
+    built(
+        Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "text/html; charset=utf-8")
+            .body(Body::from(page)),
+    )
+}
+
+async fn get_file(
+    key: String,
+    range: Option<String>,
+    accept: Option<String>,
+    gateway: Gateway,
+) -> Result<Response<Body>, Infallible> {
+    let (file, manifest) = match gateway.find(&key).await {
+        Ok(found) => found,
+        Err(reason) => return Ok(text(StatusCode::NOT_FOUND, format!("File not found: {reason}"))),
+    };
+    if range.is_none() && file.is_directory() {
+        let node = manifest.clone();
+        let directory = gateway
+            .blocking(move |gateway| {
+                let mut bytes = Vec::new();
+                gateway.storage.reader(&node, None).read_to_end(&mut bytes).ok()?;
+                Directory::from_bytes(&bytes)
+            })
+            .await;
+        if let Ok(Some(directory)) = directory {
+            let wants_json = accept.is_some_and(|accept| accept.contains("application/json"));
+            return Ok(directory_listing(&directory, wants_json));
+        }
+    }
+
+    // the body is read from sled a chunk at a time while it's sent
+    let size = file.size;
+    let response = match range.as_deref().and_then(|header| parse_range(header, size)) {
+        Some(Some((start, end))) => file_headers(&file)
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header("Content-Range", format!("bytes {start}-{end}/{size}"))
+            .header("Content-Length", end + 1 - start)
+            .body(body(gateway.storage.reader(&manifest, Some((start, end))))),
+        Some(None) => Response::builder()
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header("Content-Range", format!("bytes */{size}"))
+            .body(Body::empty()),
+        None => file_headers(&file)
+            .header("Content-Length", size)
+            .body(body(gateway.storage.reader(&manifest, None))),
+    };
+    Ok(built(response))
+}
+
+// metadata of a file held locally, HEAD never starts a download
+async fn head_file(key: String, gateway: Gateway) -> Result<Response<Body>, Infallible> {
+    let response = match gateway.blocking(move |gateway| StoredFile::load(&gateway.db, &key)).await {
+        Ok(Some(file)) => built(file_headers(&file).header("Content-Length", file.size).body(Body::empty())),
+        Ok(None) => empty(StatusCode::NOT_FOUND),
+        Err(reason) => text(StatusCode::INTERNAL_SERVER_ERROR, reason),
+    };
+    Ok(response)
+}
+
+async fn list_files(gateway: Gateway) -> Result<Response<Body>, Infallible> {
+    let files = gateway.blocking(|gateway| {
+        gateway
+            .db
+            .iter()
+            .filter_map(|entry| entry.ok())
+            .filter_map(|(key, value)| {
+                let key = String::from_utf8_lossy(&key).to_string();
+                Some(FileInfo::new(&key, &StoredFile::from_bytes(&value)?))
+            })
+            .collect::<Vec<_>>()
+    });
+    let response = match files.await {
+        Ok(files) => json(StatusCode::OK, &files),
+        Err(reason) => text(StatusCode::INTERNAL_SERVER_ERROR, reason),
+    };
+    Ok(response)
+}
+
+// whole content of a small form field
+async fn read_part(part: Part) -> Result<Vec<u8>, warp::Error> {
+    part.stream()
+        .try_fold(Vec::new(), |mut data, mut buf| async move {
+            while buf.has_remaining() {
+                let len = buf.chunk().len();
+                data.extend_from_slice(buf.chunk());
+                buf.advance(len);
+            }
+            Ok(data)
+        })
+        .await
+}
+
+// write an uploaded file to `path` as it comes in
+async fn spool_part(part: Part, path: &Path) -> Result<(), String> {
+    let mut file = tokio::fs::File::create(path).await.map_err(|e| e.to_string())?;
+    let mut stream = part.stream();
+    while let Some(buf) = stream.next().await {
+        let mut buf = buf.map_err(|e| e.to_string())?;
+        while buf.has_remaining() {
+            let len = buf.chunk().len();
+            file.write_all(buf.chunk()).await.map_err(|e| e.to_string())?;
+            buf.advance(len);
+        }
+    }
+    file.flush().await.map_err(|e| e.to_string())
+}
+
+// multipart form with a `file` part and an optional `key` part
+async fn upload_file(
+    origin: Option<String>,
+    host: Option<String>,
+    form: FormData,
+    gateway: Gateway,
+) -> Result<Response<Body>, Infallible> {
+    // any page can post a multipart form without a preflight, so only the origin tells them apart
+    if foreign(origin.as_deref(), host.as_deref()) {
+        return Ok(text(StatusCode::FORBIDDEN, "Upload from the node's own origin"));
+    }
+    let spool = std::env::temp_dir().join(format!("rust_dfs-upload-{:016x}", rand::random::<u64>()));
+    let response = store_upload(form, &gateway, &spool).await;
+    let _ = tokio::fs::remove_file(&spool).await;
+    Ok(response)
+}
+
+async fn store_upload(mut parts: FormData, gateway: &Gateway, spool: &Path) -> Response<Body> {
+    let mut key = None;
+    let mut name = None;
+    while let Some(part) = parts.next().await {
+        let part = match part {
+            Ok(part) => part,
+            Err(e) => return text(StatusCode::BAD_REQUEST, format!("Bad multipart body: {e}")),
+        };
+        match part.name() {
+            "key" => match read_part(part).await {
+                Ok(data) => key = Some(String::from_utf8_lossy(&data).trim().to_string()).filter(|k| !k.is_empty()),
+                Err(e) => return text(StatusCode::BAD_REQUEST, format!("Failed to read upload: {e}")),
+            },
+            "file" => {
+                let filename = part.filename().unwrap_or("upload").to_string();
+                if let Err(e) = spool_part(part, spool).await {
+                    return text(StatusCode::BAD_REQUEST, format!("Failed to read upload: {e}"));
+                }
+                name = Some(filename);
+            }
+            _ => {}
+        }
+    }
+
+    let Some(name) = name else {
+        return text(StatusCode::BAD_REQUEST, "Missing `file` part");
+    };
+    let (reply, done) = oneshot::channel();
+    let path = spool.to_path_buf();
+    if gateway.commands.send(Command::Put { key, name, path, reply }).is_err() {
+        return text(StatusCode::SERVICE_UNAVAILABLE, "Node is shutting down");
+    }
+    match done.await {
+        Ok(Ok(key)) => match gateway.blocking(move |gateway| Some(FileInfo::new(&key, &StoredFile::load(&gateway.db, &key)?))).await {
+            Ok(Some(info)) => json(StatusCode::CREATED, &info),
+            Ok(None) => text(StatusCode::INTERNAL_SERVER_ERROR, "Stored file is missing"),
+            Err(reason) => text(StatusCode::INTERNAL_SERVER_ERROR, reason),
+        },
+        Ok(Err(reason)) => text(StatusCode::INTERNAL_SERVER_ERROR, reason),
+        Err(_) => text(StatusCode::SERVICE_UNAVAILABLE, "Node is shutting down"),
+    }
+}
+
+async fn delete_file(key: String, gateway: Gateway) -> Result<Response<Body>, Infallible> {
+    let (reply, done) = oneshot::channel();
+    if gateway.commands.send(Command::Delete { key, reply }).is_err() {
+        return Ok(text(StatusCode::SERVICE_UNAVAILABLE, "Node is shutting down"));
+    }
+    let response = match done.await {
+        Ok(true) => empty(StatusCode::NO_CONTENT),
+        Ok(false) => text(StatusCode::NOT_FOUND, "File not found"),
+        Err(_) => text(StatusCode::SERVICE_UNAVAILABLE, "Node is shutting down"),
+    };
+    Ok(response)
+}
+
+// running and paused downloads, all of them or the one under `key`
+async fn downloads(key: Option<String>, gateway: Gateway) -> Result<Response<Body>, Infallible> {
+    let (reply, done) = oneshot::channel();
+    if gateway.commands.send(Command::Progress { reply }).is_err() {
+        return Ok(text(StatusCode::SERVICE_UNAVAILABLE, "Node is shutting down"));
+    }
+    let Ok(mut progress) = done.await else {
+        return Ok(text(StatusCode::SERVICE_UNAVAILABLE, "Node is shutting down"));
+    };
+    let response = match key {
+        None => json(StatusCode::OK, &progress),
+        Some(key) => match progress.iter().position(|p| p.key == key) {
+            Some(i) => json(StatusCode::OK, &progress.swap_remove(i)),
+            None => text(StatusCode::NOT_FOUND, "No download of this file"),
+        },
+    };
+    Ok(response)
+}
+
+async fn cancel_download(key: String, gateway: Gateway) -> Result<Response<Body>, Infallible> {
+    let (reply, done) = oneshot::channel();
+    if gateway.commands.send(Command::Cancel { key, reply }).is_err() {
+        return Ok(text(StatusCode::SERVICE_UNAVAILABLE, "Node is shutting down"));
+    }
+    let response = match done.await {
+        Ok(true) => empty(StatusCode::NO_CONTENT),
+        Ok(false) => text(StatusCode::NOT_FOUND, "No download of this file"),
+        Err(_) => text(StatusCode::SERVICE_UNAVAILABLE, "Node is shutting down"),
+    };
+    Ok(response)
+}
+
+// the peer table, all of it or the entry of one peer
+async fn peers(peer: Option<String>, gateway: Gateway) -> Result<Response<Body>, Infallible> {
+    let (reply, done) = oneshot::channel();
+    if gateway.commands.send(Command::Peers { reply }).is_err() {
+        return Ok(text(StatusCode::SERVICE_UNAVAILABLE, "Node is shutting down"));
+    }
+    let Ok(mut peers) = done.await else {
+        return Ok(text(StatusCode::SERVICE_UNAVAILABLE, "Node is shutting down"));
+    };
+    let response = match peer {
+        None => json(StatusCode::OK, &peers),
+        Some(peer) => match peers.iter().position(|p| p.peer_id == peer) {
+            Some(i) => json(StatusCode::OK, &peers.swap_remove(i)),
+            None => text(StatusCode::NOT_FOUND, "Peer not known"),
+        },
+    };
+    Ok(response)
+}
+
+async fn metrics(gateway: Gateway) -> Result<Response<Body>, Infallible> {
+    let (reply, done) = oneshot::channel();
+    if gateway.commands.send(Command::Metrics { reply }).is_err() {
+        return Ok(text(StatusCode::SERVICE_UNAVAILABLE, "Node is shutting down"));
+    }
+    let response = match done.await {
+        Ok(body) => built(
+            Response::builder()
+                .header("Content-Type", "application/openmetrics-text; version=1.0.0; charset=utf-8")
+                .body(Body::from(body)),
+        ),
+        Err(_) => text(StatusCode::SERVICE_UNAVAILABLE, "Node is shutting down"),
+    };
+    Ok(response)
+}
+
+// whether a browser page served from somewhere other than this gateway sent the request
+fn foreign(origin: Option<&str>, host: Option<&str>) -> bool {
+    origin.is_some_and(|origin| host.is_none_or(|host| origin != format!("http://{host}")))
+}
+
+// whether a browser page from elsewhere sent the request. Pages can post text/plain without
+// asking first, JSON needs a preflight we never answer
+fn cross_site(content_type: Option<&str>, origin: Option<&str>, host: Option<&str>) -> bool {
+    let json = content_type
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"));
+    !json || foreign(origin, host)
+}
+
+// one JSON-RPC request of the control API
+async fn rpc(
+    content_type: Option<String>,
+    origin: Option<String>,
+    host: Option<String>,
+    body: warp::hyper::body::Bytes,
+    gateway: Gateway,
+) -> Result<Response<Body>, Infallible> {
+    let Some(access) = &gateway.rpc else {
+        return Ok(text(StatusCode::NOT_FOUND, "POST /rpc is off, start the node with --http-rpc"));
+    };
+    if cross_site(content_type.as_deref(), origin.as_deref(), host.as_deref()) {
+        return Ok(text(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Send application/json from the node's own origin"));
+    }
+    let response = match control::handle(&String::from_utf8_lossy(&body), &gateway.commands, access).await {
+        Some(response) => built(
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(Body::from(response)),
+        ),
+        // a notification, nothing to answer
+        None => empty(StatusCode::NO_CONTENT),
+    };
+    Ok(response)
+}
+
+/// Binds the REST gateway and returns the address it listens on and the server future.
+pub fn bind(
+    addr: SocketAddr,
+    db: sled::Db,
+    storage: Storage,
+    cache: Cache,
+    commands: mpsc::UnboundedSender<Command>,
+    rpc_access: Option<PutAccess>,
+) -> Result<(SocketAddr, impl std::future::Future<Output = ()>), warp::Error> {
+    let gateway = Gateway { db, storage, cache, commands, rpc: rpc_access };
+    let state = warp::any().map(move || gateway.clone());
+
+    let get = warp::get()
+        .and(warp::path!("file" / String))
+        .and(warp::header::optional::<String>("range"))
+        .and(warp::header::optional::<String>("accept"))
+        .and(state.clone())
+        .and_then(get_file);
+    let head = warp::head()
+        .and(warp::path!("file" / String))
+        .and(state.clone())
+        .and_then(head_file);
+    let list = warp::get()
+        .and(warp::path!("files"))
+        .and(state.clone())
+        .and_then(list_files);
+    let upload = warp::post()
+        .and(warp::path!("file"))
+        .and(warp::header::optional::<String>("origin"))
+        .and(warp::header::optional::<String>("host"))
+        .and(warp::multipart::form().max_length(MAX_UPLOAD_BYTES))
+        .and(state.clone())
+        .and_then(upload_file);
+    let delete = warp::delete()
+        .and(warp::path!("file" / String))
+        .and(state.clone())
+        .and_then(delete_file);
+    let all_downloads = warp::get()
+        .and(warp::path!("downloads"))
+        .map(|| None)
+        .and(state.clone())
+        .and_then(downloads);
+    let one_download = warp::get()
+        .and(warp::path!("downloads" / String))
+        .map(Some)
+        .and(state.clone())
+        .and_then(downloads);
+    let cancel = warp::delete()
+        .and(warp::path!("downloads" / String))
+        .and(state.clone())
+        .and_then(cancel_download);
+    let all_peers = warp::get()
+        .and(warp::path!("peers"))
+        .map(|| None)
+        .and(state.clone())
+        .and_then(peers);
+    let one_peer = warp::get()
+        .and(warp::path!("peers" / String))
+        .map(Some)
+        .and(state.clone())
+        .and_then(peers);
+    let metrics = warp::get()
+        .and(warp::path!("metrics"))
+        .and(state.clone())
+        .and_then(metrics);
+    let rpc = warp::post()
+        .and(warp::path!("rpc"))
+        .and(warp::header::optional::<String>("content-type"))
+        .and(warp::header::optional::<String>("origin"))
+        .and(warp::header::optional::<String>("host"))
+        .and(warp::body::content_length_limit(MAX_RPC_BYTES))
+        .and(warp::body::bytes())
+        .and(state)
+        .and_then(rpc);
+
+    warp::serve(
+        get.or(head)
+            .or(list)
+            .or(upload)
+            .or(delete)
+            .or(all_downloads)
+            .or(one_download)
+            .or(cancel)
+            .or(all_peers)
+            .or(one_peer)
+            .or(metrics)
+            .or(rpc),
+    )
+    .try_bind_ephemeral(addr)
+}
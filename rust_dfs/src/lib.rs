@@ -0,0 +1,18 @@
+pub mod cache;
+pub mod chunk;
+pub mod control;
+pub mod crypto;
+pub mod directory;
+pub mod gateway;
+pub mod metrics;
+pub mod nat;
+pub mod node;
+pub mod peers;
+pub mod protocol;
+pub mod replication;
+pub mod reputation;
+pub mod storage;
+pub mod stored_file;
+pub mod transfer;
+
+pub use libp2p_examples::{config, sled_store, transport};
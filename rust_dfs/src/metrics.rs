@@ -0,0 +1,132 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use libp2p::metrics::{Recorder, Registry};
+use prometheus_client::{
+    encoding::{text, EncodeLabelSet},
+    metrics::{
+        counter::Counter,
+        family::Family,
+        gauge::Gauge,
+        histogram::{exponential_buckets, Histogram},
+    },
+};
+
+// put and get latencies from 10 ms up to about 3 minutes
+fn seconds() -> Histogram {
+    Histogram::new(exponential_buckets(0.01, 2.0, 15))
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct Outcome {
+    outcome: &'static str, // ok or error
+}
+
+impl Outcome {
+    fn of(ok: bool) -> Self {
+        Outcome { outcome: if ok { "ok" } else { "error" } }
+    }
+}
+
+/// Size of the local store when `/metrics` is scraped.
+pub struct StoreStats {
+    pub files: usize,
+    pub bytes: u64,
+    pub quota: Option<u64>,
+    pub under_replicated: usize,
+}
+
+// libp2p's swarm and protocol metrics and our own, served as Prometheus text on /metrics
+pub struct Metrics {
+    registry: Registry,
+    libp2p: libp2p::metrics::Metrics,
+    puts: Family<Outcome, Counter>,
+    put_seconds: Histogram,
+    gets: Family<Outcome, Counter>,
+    get_seconds: Histogram,
+    served: Counter,   // chunk bytes sent to peers
+    received: Counter, // chunk bytes peers sent us, rejected ones included
+    files: Gauge,
+    stored_bytes: Gauge,
+    quota_bytes: Gauge,
+    under_replicated: Gauge,
+    gets_started: HashMap<String, Instant>, // downloads running, timed from their last start
+}
+
+impl Metrics {
+    // `registry` already holds the transport bandwidth counters of the swarm builder
+    pub fn new(mut registry: Registry) -> Self {
+        let libp2p = libp2p::metrics::Metrics::new(&mut registry);
+        let mut metrics = Metrics {
+            registry,
+            libp2p,
+            puts: Family::default(),
+            put_seconds: seconds(),
+            gets: Family::default(),
+            get_seconds: seconds(),
+            served: Counter::default(),
+            received: Counter::default(),
+            files: Gauge::default(),
+            stored_bytes: Gauge::default(),
+            quota_bytes: Gauge::default(),
+            under_replicated: Gauge::default(),
+            gets_started: HashMap::new(),
+        };
+        let own = metrics.registry.sub_registry_with_prefix("rust_dfs");
+        own.register("puts", "Files stored on this node, by outcome", metrics.puts.clone());
+        own.register("put_seconds", "Time to chunk, store and announce a file", metrics.put_seconds.clone());
+        own.register("gets", "Downloads from the network, by outcome", metrics.gets.clone());
+        own.register("get_seconds", "Time from looking up providers to a finished download", metrics.get_seconds.clone());
+        own.register("chunk_bytes_served", "Chunk bytes sent to other peers", metrics.served.clone());
+        own.register("chunk_bytes_received", "Chunk bytes received from other peers", metrics.received.clone());
+        own.register("files", "Files stored on this node", metrics.files.clone());
+        own.register("stored_bytes", "Bytes of the chunks stored on this node", metrics.stored_bytes.clone());
+        own.register("quota_bytes", "Storage quota, 0 if none", metrics.quota_bytes.clone());
+        own.register("under_replicated_files", "Files with fewer live providers than the replication factor", metrics.under_replicated.clone());
+        metrics
+    }
+
+    // a swarm or protocol event, for the libp2p_ metrics
+    pub fn record<E>(&self, event: &E)
+    where
+        libp2p::metrics::Metrics: Recorder<E>,
+    {
+        self.libp2p.record(event);
+    }
+
+    // puts are written off the swarm loop, which learns how long they took afterwards
+    pub fn put(&self, took: Duration, ok: bool) {
+        self.puts.get_or_create(&Outcome::of(ok)).inc();
+        self.put_seconds.observe(took.as_secs_f64());
+    }
+
+    pub fn get_started(&mut self, key: &str) {
+        self.gets_started.insert(key.to_string(), Instant::now());
+    }
+
+    pub fn get_finished(&mut self, key: &str, ok: bool) {
+        if let Some(started) = self.gets_started.remove(key) {
+            self.gets.get_or_create(&Outcome::of(ok)).inc();
+            self.get_seconds.observe(started.elapsed().as_secs_f64());
+        }
+    }
+
+    pub fn served(&self, bytes: usize) {
+        self.served.inc_by(bytes as u64);
+    }
+
+    pub fn received(&self, bytes: usize) {
+        self.received.inc_by(bytes as u64);
+    }
+
+    pub fn encode(&self, store: StoreStats) -> String {
+        self.files.set(store.files as i64);
+        self.stored_bytes.set(store.bytes as i64);
+        self.quota_bytes.set(store.quota.unwrap_or(0) as i64);
+        self.under_replicated.set(store.under_replicated as i64);
+        let mut out = String::new();
+        text::encode(&mut out, &self.registry).expect("writing to a String can't fail");
+        out
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::{HashMap, HashSet};
+use libp2p::{autonat::NatStatus, core::transport::ListenerId, multiaddr::Protocol, swarm::ConnectionId, Multiaddr, PeerId};
+
+// relays we hold a reservation on at once, one more keeps us reachable if a relay goes away
+pub const MAX_RESERVATIONS: usize = 2;
+
+/// How a node takes part in circuit relay v2, from `--relay <mode>`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RelayMode {
+    Off,
+    // reserve a slot on relay peers once AutoNAT finds us unreachable
+    #[default]
+    Auto,
+    // always reserve, for nodes known to be behind NAT
+    Client,
+    // relay connections for other peers, for nodes with a public address
+    Server,
+}
+
+impl RelayMode {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "off" => Ok(RelayMode::Off),
+            "auto" => Ok(RelayMode::Auto),
+            "client" => Ok(RelayMode::Client),
+            "server" => Ok(RelayMode::Server),
+            other => Err(format!("unknown relay mode {other}, use off, auto, client or server")),
+        }
+    }
+}
+
+// whether a peer can reach us directly, and the relays that forward connections when it can't
+pub struct Nat {
+    pub mode: RelayMode,
+    pub status: NatStatus,
+    relays: HashMap<PeerId, Multiaddr>, // peers offering relay v2, with an address to reach them
+    pinned: HashSet<PeerId>,            // relays from --listen <relay>/p2p-circuit, used in every mode
+    reservations: HashMap<PeerId, ListenerId>,
+    circuits: HashMap<PeerId, Vec<ConnectionId>>, // relayed connections, until hole punching replaces them
+}
+
+impl Nat {
+    pub fn new(mode: RelayMode) -> Self {
+        Nat {
+            mode,
+            status: NatStatus::Unknown,
+            relays: HashMap::new(),
+            pinned: HashSet::new(),
+            reservations: HashMap::new(),
+            circuits: HashMap::new(),
+        }
+    }
+
+    pub fn needs_relay(&self) -> bool {
+        match self.mode {
+            RelayMode::Client => true,
+            RelayMode::Auto => self.status == NatStatus::Private,
+            RelayMode::Off | RelayMode::Server => false,
+        }
+    }
+
+    // returns true if the relay wasn't known yet
+    pub fn add_relay(&mut self, peer: PeerId, addr: Multiaddr) -> bool {
+        self.relays.insert(peer, addr).is_none()
+    }
+
+    // a relay given as a listen address, `addr` is the part before /p2p-circuit
+    pub fn pin(&mut self, relay: PeerId, addr: Multiaddr) {
+        self.pinned.insert(relay);
+        self.relays.insert(relay, addr);
+    }
+
+    pub fn is_relay(&self, peer: &PeerId) -> bool {
+        self.relays.contains_key(peer)
+    }
+
+    // relays to reserve a slot on, with their address, those we have no reservation with yet.
+    // Pinned relays always, others while we need them and up to MAX_RESERVATIONS
+    pub fn wanted(&self) -> Vec<(PeerId, Multiaddr)> {
+        let free = |peer: &&PeerId| !self.reservations.contains_key(*peer);
+        let mut wanted: Vec<&PeerId> = self.pinned.iter().filter(free).collect();
+        if self.needs_relay() {
+            let room = MAX_RESERVATIONS.saturating_sub(self.reservations.len() + wanted.len());
+            wanted.extend(self.relays.keys().filter(|peer| !self.pinned.contains(*peer)).filter(free).take(room));
+        }
+        wanted
+            .into_iter()
+            .map(|peer| (*peer, self.relays[peer].clone()))
+            .collect()
+    }
+
+    pub fn reserved(&mut self, relay: PeerId, listener: ListenerId) {
+        self.reservations.insert(relay, listener);
+    }
+
+    // a reservation's listener closed, the relay may be tried again later. Returns the relay
+    pub fn closed(&mut self, listener: ListenerId) -> Option<PeerId> {
+        let relay = self.reservations.iter().find(|(_, id)| **id == listener).map(|(peer, _)| *peer)?;
+        self.reservations.remove(&relay);
+        Some(relay)
+    }
+
+    pub fn circuit_opened(&mut self, peer: PeerId, connection: ConnectionId) {
+        self.circuits.entry(peer).or_default().push(connection);
+    }
+
+    pub fn circuit_closed(&mut self, peer: PeerId, connection: ConnectionId) {
+        if let Some(circuits) = self.circuits.get_mut(&peer) {
+            circuits.retain(|id| *id != connection);
+            if circuits.is_empty() {
+                self.circuits.remove(&peer);
+            }
+        }
+    }
+
+    // relayed connections to a peer we now reach directly. Relays limit how long and how much
+    // a circuit carries, requests spread over both connections would fail on it
+    pub fn replaced(&mut self, peer: PeerId) -> Vec<ConnectionId> {
+        self.circuits.remove(&peer).unwrap_or_default()
+    }
+
+    // listeners of the reservations we made for AutoNAT, once we turn out to be reachable without them
+    pub fn release(&mut self) -> Vec<ListenerId> {
+        let released: Vec<PeerId> = self.reservations.keys().filter(|peer| !self.pinned.contains(*peer)).copied().collect();
+        released.iter().filter_map(|peer| self.reservations.remove(peer)).collect()
+    }
+}
+
+pub fn is_relayed(addr: &Multiaddr) -> bool {
+    addr.iter().any(|protocol| protocol == Protocol::P2pCircuit)
+}
+
+// the relay of a <relay address>/p2p/<relay>/p2p-circuit address, and its address
+pub fn relay_of(addr: &Multiaddr) -> Option<(PeerId, Multiaddr)> {
+    let mut relay_addr = Multiaddr::empty();
+    let mut relay = None;
+    for protocol in addr.iter() {
+        match protocol {
+            Protocol::P2p(peer) => relay = Some(peer),
+            Protocol::P2pCircuit => return Some((relay?, relay_addr)),
+            other => relay_addr.push(other),
+        }
+    }
+    None
+}
+
+// where we listen to be reached through a relay
+pub fn circuit(relay_addr: &Multiaddr, relay: PeerId) -> Multiaddr {
+    relay_addr.clone().with(Protocol::P2p(relay)).with(Protocol::P2pCircuit)
+}
@@ -0,0 +1,1723 @@
+use std::{
+    error::Error,
+    fs,
+    io::{BufWriter, Read, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use futures::{
+    stream::{FuturesUnordered, StreamExt},
+    FutureExt,
+};
+use libp2p::{
+    allow_block_list, autonat, connection_limits, dcutr, identify, kad,
+    kad::{store::RecordStore, Mode},
+    mdns,
+    metrics::Registry,
+    multiaddr::Protocol,
+    noise, ping, relay,
+    request_response::{self, ProtocolSupport},
+    swarm::{behaviour::toggle::Toggle, dial_opts::DialOpts, NetworkBehaviour, SwarmEvent},
+    yamux, Multiaddr, PeerId, Swarm,
+};
+use tokio::{
+    io::{self, AsyncBufReadExt},
+    select,
+    sync::{mpsc, oneshot},
+};
+use std::collections::{HashMap, HashSet};
+use serde_json::{json, Value};
+
+use crate::{cache, chunk, config, control, crypto, directory, gateway, nat, replication, reputation, stored_file, transfer, transport};
+use crate::cache::Cache;
+use crate::config::NodeConfig;
+use crate::chunk::{Download, Manifest};
+use crate::control::{Call, PutAccess};
+use crate::crypto::{Capability, Encryptor, Lock, Unlock};
+use crate::directory::{Directory, Entry, Kind};
+use crate::gateway::{Command, Waiters};
+use crate::metrics::{Metrics, StoreStats};
+use crate::nat::{Nat, RelayMode};
+use crate::peers::Peers;
+use crate::protocol::{FileProtocol, FileRequest, FileResponse, IDENTIFY_PROTOCOL};
+use crate::replication::Replication;
+use crate::reputation::{Offence, RateLimiter, Reputation};
+use crate::sled_store::SledStore;
+use crate::storage::Storage;
+use crate::stored_file::StoredFile;
+use crate::transfer::{Outcome, TransferBehaviour, Transfers};
+
+#[derive(NetworkBehaviour)]
+struct Behaviour {
+    // connections over the limits or to banned peers are refused before the others see them
+    limits: connection_limits::Behaviour,
+    banned: allow_block_list::Behaviour<allow_block_list::BlockedPeers>,
+    kademlia: kad::Behaviour<SledStore>,
+    mdns: Toggle<mdns::tokio::Behaviour>, // off with --no-mdns
+    transfer: TransferBehaviour,
+    identify: identify::Behaviour,
+    ping: ping::Behaviour,
+    autonat: autonat::Behaviour,
+    relay_client: relay::client::Behaviour,
+    relay: Toggle<relay::Behaviour>, // only with --relay server
+    dcutr: dcutr::Behaviour,
+}
+
+// local state next to the swarm, shared by the event loop, the CLI and the gateway commands
+struct Node {
+    peer_id: PeerId,
+    listening: Vec<Multiaddr>, // our listen addresses, with /p2p/<peer id>
+    db: sled::Db,
+    storage: Storage,
+    cache: Cache,
+    quota: Option<u64>, // bytes of stored files before unpinned ones are evicted
+    retrieved_dir: PathBuf,
+    peers: Peers,
+    transfers: Transfers, // files being pulled from their providers
+    waiters: Waiters,
+    gets: HashMap<String, Vec<control::Reply>>, // control API gets waiting for a download
+    wanted: HashSet<String>, // downloads asked for by GET_FILE or a get, written to disk once done
+    replication: Replication,
+    http_addr: SocketAddr,
+    secret: x25519_dalek::StaticSecret, // opens files encrypted to this node
+    unlocks: HashMap<String, Unlock>, // how to decrypt downloads started with a capability or passphrase
+    restores: HashMap<String, HashSet<String>>, // directories being fetched, with the keys below them still missing
+    nat: Nat,
+    reputation: Reputation,
+    request_limit: RateLimiter, // manifest, chunk and replicate requests per peer
+    put_limit: RateLimiter,     // DHT records and provider records per peer
+    metrics: Metrics,
+    puts: mpsc::UnboundedSender<Put>, // puts written off the loop come back through here
+    writing: usize,                   // puts still being written, GC waits for them
+}
+
+/// How to run a node, from the command line or set by a program running nodes in-process.
+pub struct Options {
+    pub db_path: String,
+    pub http_addr: SocketAddr,
+    pub factor: usize,
+    pub quota: Option<u64>,
+    pub retrieved_dir: PathBuf,
+    pub max_connections: u32,
+    pub request_rate: u32,
+    pub socket: PathBuf,              // the control socket
+    pub http_rpc: bool,               // POST /rpc answers, off unless --http-rpc
+    pub rpc_put_dir: Option<PathBuf>, // the only files a put over POST /rpc may read
+    pub mdns: bool,                   // off with --no-mdns, peers then come from --bootstrap and the DHT
+    pub stdin: bool,                  // read CLI commands from stdin
+    pub repair_interval: Duration,    // how often providers are counted
+    pub resume_interval: Duration,    // how often paused downloads are tried again
+    pub relay: RelayMode,             // --relay <mode>, or relay in the config file
+    pub network: NodeConfig,
+}
+
+impl Options {
+    pub fn from_args() -> Result<Self, Box<dyn Error>> {
+        Self::parse(std::env::args().skip(1))
+    }
+
+    // rust_dfs [db_path] [--http <addr>] [--replication <n>] [--quota <size>] [--retrieved <dir>]
+    //          [--max-connections <n>] [--rate-limit <requests per second>] [--socket <path>]
+    //          [--no-mdns] [--http-rpc] [--rpc-put-dir <dir>] [--config <file>] [--key <file>]
+    //          [--transport <names>] [--relay <mode>] [--listen <multiaddr>]... [--bootstrap <multiaddr>]...
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, Box<dyn Error>> {
+        let network = NodeConfig::parse(args)?;
+        let mut db_path = "file_store/db".to_string();
+        let mut http_addr: SocketAddr = "127.0.0.1:8080".parse()?;
+        let mut factor = replication::DEFAULT_FACTOR;
+        let mut quota = None;
+        let mut retrieved_dir = PathBuf::from(cache::DEFAULT_RETRIEVED_DIR);
+        let mut max_connections = reputation::DEFAULT_MAX_CONNECTIONS;
+        let mut request_rate = reputation::DEFAULT_REQUEST_RATE;
+        let mut socket = None;
+        let mut mdns = true;
+        let mut http_rpc = false;
+        let mut rpc_put_dir = None;
+        let mut relay = network.relay.clone();
+        let mut args = network.args.iter().cloned();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--http" => http_addr = args.next().ok_or("--http needs an address")?.parse()?,
+                "--replication" => factor = args.next().ok_or("--replication needs a number")?.parse()?,
+                "--quota" => {
+                    let size = args.next().ok_or("--quota needs a size")?;
+                    quota = Some(cache::parse_size(&size).ok_or(format!("invalid quota {size}, use bytes or a K, M or G suffix"))?);
+                }
+                "--retrieved" => retrieved_dir = args.next().ok_or("--retrieved needs a directory")?.into(),
+                "--max-connections" => max_connections = args.next().ok_or("--max-connections needs a number")?.parse()?,
+                "--rate-limit" => request_rate = args.next().ok_or("--rate-limit needs requests per second, 0 for none")?.parse()?,
+                "--socket" => socket = Some(PathBuf::from(args.next().ok_or("--socket needs a path")?)),
+                "--no-mdns" => mdns = false,
+                "--http-rpc" => http_rpc = true,
+                "--rpc-put-dir" => rpc_put_dir = Some(PathBuf::from(args.next().ok_or("--rpc-put-dir needs a directory")?)),
+                "--relay" => relay = Some(args.next().ok_or("--relay needs off, auto, client or server")?),
+                // a typo would otherwise become the database path
+                _ if arg.starts_with("--") => return Err(format!("unknown option {arg}").into()),
+                _ => db_path = arg,
+            }
+        }
+        // the control socket sits next to the database unless --socket moves it
+        let socket = socket.unwrap_or_else(|| PathBuf::from(format!("{db_path}.sock")));
+        Ok(Options {
+            db_path,
+            http_addr,
+            factor,
+            quota,
+            retrieved_dir,
+            max_connections,
+            request_rate,
+            socket,
+            http_rpc,
+            rpc_put_dir,
+            mdns,
+            stdin: true,
+            repair_interval: replication::CHECK_INTERVAL,
+            resume_interval: transfer::RESUME_INTERVAL,
+            relay: relay.map(|mode| RelayMode::parse(&mode)).transpose()?.unwrap_or_default(),
+            network,
+        })
+    }
+}
+
+/// A started node as its caller sees it, commands reach the swarm loop the way the gateway's do.
+pub struct Handle {
+    pub peer_id: PeerId,
+    pub commands: mpsc::UnboundedSender<Command>,
+}
+
+/// Opens the database, builds the swarm and starts the HTTP gateway and the control socket.
+/// Returns the handle and the swarm loop, the node runs while the loop is polled.
+pub fn start(options: Options) -> Result<(Handle, impl Future<Output = ()>), Box<dyn Error>> {
+    let network = options.network;
+    let http_addr = options.http_addr;
+    let max_connections = options.max_connections;
+
+    // the peer ID stays the same across restarts, provider records in the DHT keep pointing at us
+    let key_path = network.key.clone().unwrap_or_else(|| format!("{}.key", options.db_path).into());
+    let keypair = config::keypair(&key_path)?;
+    let socket = options.socket;
+    let db = sled::open(&options.db_path).expect("Failed to open sled database");
+    let storage = Storage::open(&db).expect("Failed to open sled trees");
+    let cache = Cache::open(&db).expect("Failed to open sled trees");
+    let transfers = Transfers::open(&db).expect("Failed to open sled trees");
+    let reputation = Reputation::open(&db).expect("Failed to open sled trees");
+    let secret = crypto::node_secret(&db).expect("Failed to load encryption key");
+    match stored_file::migrate(&db, &storage) {
+        Ok(0) => {}
+        Ok(count) => println!("Migrated {count} stored files to format v{}", stored_file::FORMAT_VERSION),
+        Err(e) => eprintln!("Failed to migrate stored files: {e}"),
+    }
+
+    let mut registry = Registry::default();
+    let mut swarm = libp2p::SwarmBuilder::with_existing_identity(keypair)
+        .with_tokio()
+        .with_other_transport(|key| transport::build(key, network.transports))?
+        // dialing and listening through relays, /p2p-circuit addresses
+        .with_relay_client(noise::Config::new, yamux::Config::default)?
+        // bytes in and out per transport, for /metrics
+        .with_bandwidth_metrics(&mut registry)
+        .with_behaviour(|key, relay_client| {
+            let peer_id = key.public().to_peer_id();
+            // records other peers put are handed to us to check before they are stored
+            let mut kad_config = kad::Config::new(kad::PROTOCOL_NAME);
+            kad_config.set_record_filtering(kad::StoreInserts::FilterBoth);
+            Ok(Behaviour {
+                limits: connection_limits::Behaviour::new(
+                    connection_limits::ConnectionLimits::default()
+                        .with_max_established(Some(max_connections))
+                        .with_max_established_per_peer(Some(reputation::MAX_CONNECTIONS_PER_PEER))
+                        .with_max_pending_incoming(Some(reputation::MAX_PENDING_INCOMING)),
+                ),
+                banned: allow_block_list::Behaviour::default(),
+                kademlia: kad::Behaviour::with_config(
+                    peer_id,
+                    // DHT records and provider records live next to the files, so they survive a restart
+                    SledStore::new(peer_id, &db)?,
+                    kad_config,
+                ),
+                mdns: Toggle::from(
+                    options.mdns.then(|| mdns::tokio::Behaviour::new(mdns::Config::default(), peer_id)).transpose()?,
+                ),
+                transfer: request_response::Behaviour::new(
+                    [(FileProtocol, ProtocolSupport::Full)],
+                    request_response::Config::default(),
+                ),
+                // tells peers our listen addresses and what they see us as, AutoNAT and DCUtR build on it
+                identify: identify::Behaviour::new(
+                    identify::Config::new(IDENTIFY_PROTOCOL.to_string(), key.public())
+                        .with_agent_version(format!("rust_dfs/{}", env!("CARGO_PKG_VERSION"))),
+                ),
+                // round trip times for LIST_PEERS
+                ping: ping::Behaviour::new(ping::Config::new()),
+                autonat: autonat::Behaviour::new(peer_id, autonat::Config::default()),
+                relay_client,
+                relay: Toggle::from(
+                    (options.relay == RelayMode::Server).then(|| relay::Behaviour::new(peer_id, relay::Config::default())),
+                ),
+                dcutr: dcutr::Behaviour::new(peer_id),
+            })
+        })?
+        // keep connections open between the chunk requests of one download
+        .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
+        .build();
+
+    swarm.behaviour_mut().kademlia.set_mode(Some(Mode::Server));
+    for (peer, _, _) in reputation.bans() {
+        swarm.behaviour_mut().banned.block_peer(peer);
+    }
+
+    let mut nat = Nat::new(options.relay);
+    for addr in network.listen_addrs() {
+        // --listen <relay>/p2p-circuit reserves once the relay is connected, see reserve_relays
+        match nat::relay_of(&addr) {
+            Some((relay, relay_addr)) => nat.pin(relay, relay_addr),
+            None => {
+                swarm.listen_on(addr)?;
+            }
+        }
+    }
+    // mDNS only finds peers on the LAN, bootstrap peers are how the DHT reaches further
+    for addr in &network.bootstrap {
+        match config::peer_id(addr) {
+            Some(peer_id) => {
+                swarm.behaviour_mut().kademlia.add_address(&peer_id, addr.clone());
+            }
+            None => eprintln!("Bootstrap address {addr} has no /p2p/<peer id>, dialing it anyway"),
+        }
+        if let Err(e) = swarm.dial(addr.clone()) {
+            eprintln!("Failed to dial {addr}: {e}");
+        }
+    }
+    println!("Local Peer ID: {}", swarm.local_peer_id());
+    println!("PUT_FILE <key> <file_path>: Store a file or a whole directory in the DHT");
+    println!("  add --passphrase <p> or --to <public_key> to store it encrypted");
+    println!("GET_FILE <key>: Retrieve a file from the DHT");
+    println!("  pass a dfs1: capability instead of the key, or --passphrase <p>, to decrypt");
+    println!("LIST_FILE: List all files stored in the DHT");
+    println!("LIST_PEERS: List known peers with their state, addresses, latency, agent and protocols");
+    println!("STATUS: Show how many providers each stored file has");
+    println!("MY_KEY: Show the public key others can encrypt files to");
+    println!("PIN <key> / UNPIN <key>: Keep a file from being evicted when the quota is reached, or allow it again");
+    println!("GC: Remove orphaned sled entries and stale retrieved copies");
+    println!("DOWNLOADS: Show the progress of running and paused downloads, CANCEL <key> to give one up");
+    println!("BAN <peer_id> [reason] / UNBAN <peer_id>: Refuse or allow a peer again, BANS lists banned peers");
+    println!("http://{http_addr}/file/<key>: Retrieve a file via HTTP, fetched from the DHT if needed");
+    println!("http://{http_addr}/files: List stored files, POST /file to upload, DELETE /file/<key> to remove");
+    println!("http://{http_addr}/peers: The peer table as JSON, /peers/<peer_id> for one peer");
+    println!("http://{http_addr}/metrics: Swarm, DHT, transfer and storage metrics for Prometheus");
+    println!("http://{http_addr}/rpc: JSON-RPC control API ({}), also on the control socket", control::METHODS.join(", "));
+
+    // our provider records are kept in sled, announce them again so peers learn about us right away
+    for key in storage.file_keys() {
+        if let Some(manifest) = storage.manifest(&key) {
+            cache.adopt(&key, manifest.size);
+        }
+        if let Err(e) = swarm.behaviour_mut().kademlia.start_providing(kad::RecordKey::new(&key)) {
+            eprintln!("Failed to announce {key}: {e:?}");
+        }
+    }
+
+    // the HTTP gateway hands uploads, downloads and deletes to this loop
+    let (commands_tx, mut commands) = mpsc::unbounded_channel::<Command>();
+    // files are chunked into sled on the blocking pool, the loop only announces them
+    let (puts_tx, mut puts) = mpsc::unbounded_channel::<Put>();
+    // the servers run in the loop's task and stop with it, nothing holds the database after that
+    let mut servers = FuturesUnordered::new();
+    // the control API takes the same way in as the gateway
+    #[cfg(unix)]
+    match control::bind(&socket, commands_tx.clone()) {
+        Ok(server) => {
+            println!("Control API on {}, try: rust_dfs ctl --socket {} status", socket.display(), socket.display());
+            servers.push(server.boxed());
+        }
+        Err(e) => eprintln!("Failed to start the control API on {}: {e}", socket.display()),
+    }
+    #[cfg(not(unix))]
+    println!("No control socket at {} on this platform, use POST /rpc with --http-rpc", socket.display());
+    // anyone who can reach the gateway could drive the node through /rpc, it takes a flag
+    let rpc = options.http_rpc.then(|| options.rpc_put_dir.map_or(PutAccess::Nowhere, PutAccess::Below));
+    match gateway::bind(http_addr, db.clone(), storage.clone(), cache.clone(), commands_tx.clone(), rpc) {
+        Ok((addr, server)) => {
+            println!("HTTP gateway on http://{addr}");
+            servers.push(server.boxed());
+        }
+        Err(e) => eprintln!("Failed to start HTTP gateway on {http_addr}: {e}"),
+    }
+
+    let mut node = Node {
+        peer_id: *swarm.local_peer_id(),
+        listening: Vec::new(),
+        db,
+        storage,
+        cache,
+        quota: options.quota,
+        retrieved_dir: options.retrieved_dir,
+        peers: Peers::default(),
+        transfers,
+        waiters: Waiters::new(),
+        gets: HashMap::new(),
+        wanted: HashSet::new(),
+        replication: Replication::new(options.factor),
+        http_addr,
+        secret,
+        unlocks: HashMap::new(),
+        restores: HashMap::new(),
+        nat,
+        reputation,
+        request_limit: RateLimiter::requests(options.request_rate),
+        put_limit: RateLimiter::puts(),
+        metrics: Metrics::new(registry),
+        puts: puts_tx,
+        writing: 0,
+    };
+    enforce_quota(swarm.behaviour_mut(), &mut node, None);
+    let handle = Handle { peer_id: node.peer_id, commands: commands_tx };
+
+    // providers of every stored file are counted periodically and when a peer expires
+    let mut repair = tokio::time::interval(options.repair_interval);
+    // the routing table is refreshed through the known peers, the first tick runs right away
+    let mut bootstrap = tokio::time::interval(config::BOOTSTRAP_INTERVAL);
+    // unfinished downloads carry on once peers are found, and are retried while paused
+    let mut resume = tokio::time::interval(options.resume_interval);
+    let unfinished = node.transfers.resumable().len();
+    if unfinished > 0 {
+        println!("{unfinished} unfinished download(s), resuming once providers are found");
+    }
+
+    // without stdin, as a daemon, the node is driven through the control API
+    let mut stdin = io::BufReader::new(io::stdin()).lines();
+    let mut reading = options.stdin;
+    let run = async move {
+        loop {
+            select! {
+                line = stdin.next_line(), if reading => match line {
+                    Ok(Some(line)) => handle_input_line(swarm.behaviour_mut(), &mut node, line),
+                    _ => reading = false,
+                },
+                Some(command) = commands.recv() => {
+                    handle_command(swarm.behaviour_mut(), &mut node, command);
+                }
+                Some(put) = puts.recv() => {
+                    finish_put(swarm.behaviour_mut(), &mut node, put);
+                }
+                _ = servers.next(), if !servers.is_empty() => {}
+                _ = repair.tick() => {
+                    check_replication(swarm.behaviour_mut(), &mut node);
+                }
+                _ = bootstrap.tick() => {
+                    // nothing to do until a peer is known
+                    let _ = swarm.behaviour_mut().kademlia.bootstrap();
+                    node.peers.prune();
+                    // reservations lost since, with relays that went away, are made again elsewhere
+                    reserve_relays(&mut swarm, &mut node);
+                }
+                _ = resume.tick() => {
+                    resume_downloads(swarm.behaviour_mut(), &mut node);
+                }
+                event = swarm.select_next_some() => {
+                    record_event(&node.metrics, &event);
+                    match event {
+                        SwarmEvent::NewListenAddr { address, .. } => {
+                            // with the peer ID appended, what other nodes pass to --bootstrap
+                            let full = match address.iter().last() {
+                                Some(Protocol::P2p(_)) => address.clone(), // relayed, ends with our ID already
+                                _ => address.clone().with(Protocol::P2p(node.peer_id)),
+                            };
+                            println!("Listening on {full}");
+                            node.listening.push(full);
+                            // a relay hands its external addresses to the peers it reserves for, so a relay
+                            // is expected to listen on addresses others can reach
+                            if node.nat.mode == RelayMode::Server && !nat::is_relayed(&address) {
+                                swarm.add_external_address(address);
+                            }
+                        }
+                        SwarmEvent::ExpiredListenAddr { address, .. } => {
+                            let full = address.clone().with(Protocol::P2p(node.peer_id));
+                            node.listening.retain(|listening| *listening != address && *listening != full);
+                        }
+                        SwarmEvent::ConnectionEstablished { peer_id, connection_id, endpoint, .. } => {
+                            node.peers.connected(peer_id, connection_id, endpoint.get_remote_address().clone());
+                            if endpoint.is_relayed() {
+                                node.nat.circuit_opened(peer_id, connection_id);
+                            } else if node.nat.is_relay(&peer_id) {
+                                reserve_relays(&mut swarm, &mut node);
+                            }
+                        }
+                        SwarmEvent::ConnectionClosed { peer_id, connection_id, .. } => {
+                            node.peers.disconnected(peer_id, connection_id);
+                            node.nat.circuit_closed(peer_id, connection_id);
+                        }
+                        SwarmEvent::ListenerClosed { listener_id, reason, .. } => {
+                            if let Some(relay) = node.nat.closed(listener_id) {
+                                match reason {
+                                    Ok(()) => println!("Reservation on relay {relay} closed"),
+                                    Err(e) => eprintln!("Reservation on relay {relay} failed: {e}"),
+                                }
+                            }
+                        }
+                        SwarmEvent::Behaviour(BehaviourEvent::Identify(identify::Event::Received { peer_id, info, .. })) => {
+                            node.peers.identified(peer_id, &info);
+                            // peers behind NAT are found through the relayed addresses they list
+                            for addr in &info.listen_addrs {
+                                swarm.behaviour_mut().kademlia.add_address(&peer_id, addr.clone());
+                            }
+                            let direct = info.listen_addrs.iter().find(|addr| !nat::is_relayed(addr) && network.transports.supports(addr));
+                            if info.protocols.contains(&relay::HOP_PROTOCOL_NAME)
+                                && let Some(addr) = direct
+                                && node.nat.add_relay(peer_id, addr.clone())
+                            {
+                                println!("{peer_id} offers relaying");
+                                reserve_relays(&mut swarm, &mut node);
+                            }
+                        }
+                        SwarmEvent::Behaviour(BehaviourEvent::Ping(ping::Event { peer, result: Ok(rtt), .. })) => {
+                            node.peers.pinged(peer, rtt);
+                        }
+                        SwarmEvent::Behaviour(BehaviourEvent::Autonat(autonat::Event::StatusChanged { new, .. })) => {
+                            match &new {
+                                autonat::NatStatus::Public(addr) => println!("Reachable from the network at {addr}"),
+                                autonat::NatStatus::Private => println!("Not reachable from the network, behind NAT or a firewall"),
+                                autonat::NatStatus::Unknown => {}
+                            }
+                            node.nat.status = new;
+                            if node.nat.needs_relay() {
+                                reserve_relays(&mut swarm, &mut node);
+                            } else if node.nat.mode == RelayMode::Auto {
+                                for listener in node.nat.release() {
+                                    swarm.remove_listener(listener);
+                                }
+                            }
+                        }
+                        SwarmEvent::Behaviour(BehaviourEvent::RelayClient(relay::client::Event::ReservationReqAccepted { relay_peer_id, renewal: false, .. })) => {
+                            println!("Reachable through relay {relay_peer_id}");
+                        }
+                        SwarmEvent::Behaviour(BehaviourEvent::RelayClient(relay::client::Event::InboundCircuitEstablished { src_peer_id, .. })) => {
+                            println!("{src_peer_id} connected through a relay");
+                        }
+                        SwarmEvent::Behaviour(BehaviourEvent::Relay(relay::Event::ReservationReqAccepted { src_peer_id, renewed: false })) => {
+                            println!("Relaying for {src_peer_id}");
+                        }
+                        SwarmEvent::Behaviour(BehaviourEvent::Relay(relay::Event::CircuitReqAccepted { src_peer_id, dst_peer_id })) => {
+                            println!("Relaying a connection from {src_peer_id} to {dst_peer_id}");
+                        }
+                        SwarmEvent::Behaviour(BehaviourEvent::Dcutr(dcutr::Event { remote_peer_id, result })) => match result {
+                            Ok(_) => {
+                                println!("Direct connection to {remote_peer_id} made through hole punching");
+                                for circuit in node.nat.replaced(remote_peer_id) {
+                                    swarm.close_connection(circuit);
+                                }
+                            }
+                            Err(e) => eprintln!("Hole punching to {remote_peer_id} failed: {e}"),
+                        },
+                        SwarmEvent::Behaviour(BehaviourEvent::Mdns(mdns::Event::Discovered(list))) => {
+                            for (peer_id, multiaddr) in list {
+                                swarm.behaviour_mut().kademlia.add_address(&peer_id, multiaddr.clone());
+                                node.peers.discovered(peer_id, multiaddr);
+                            }
+                            // a new peer may hold what a paused download is missing
+                            resume_downloads(swarm.behaviour_mut(), &mut node);
+                        }
+                        SwarmEvent::Behaviour(BehaviourEvent::Mdns(mdns::Event::Expired(list))) => {
+                            for (peer_id, multiaddr) in list {
+                                swarm.behaviour_mut().kademlia.remove_address(&peer_id, &multiaddr);
+                                node.peers.expired(peer_id, &multiaddr);
+                            }
+                            // a provider may be gone, recount now rather than at the next tick
+                            check_replication(swarm.behaviour_mut(), &mut node);
+                        }
+                        SwarmEvent::Behaviour(BehaviourEvent::Kademlia(kad::Event::OutboundQueryProgressed { id, result, ..})) => {
+                            let finished = match result {
+                                kad::QueryResult::GetProviders(Ok(kad::GetProvidersOk::FoundProviders { providers, .. })) if node.replication.is_check(id) => {
+                                    node.replication.add_providers(id, providers);
+                                    None
+                                }
+                                kad::QueryResult::GetProviders(result) if node.replication.is_check(id) => {
+                                    if let Err(err) = result {
+                                        eprintln!("Failed to count providers: {err:?}");
+                                    }
+                                    if let Some((key, providers)) = node.replication.finish(id) {
+                                        repair_file(&mut swarm, &mut node, &key, providers);
+                                    }
+                                    None
+                                }
+                                kad::QueryResult::GetProviders(Ok(kad::GetProvidersOk::FoundProviders { providers, .. })) => {
+                                    let local = *swarm.local_peer_id();
+                                    let providers = providers.into_iter().filter(|peer| *peer != local);
+                                    node.transfers.add_providers(&mut swarm.behaviour_mut().transfer, id, providers)
+                                }
+                                kad::QueryResult::GetProviders(Ok(kad::GetProvidersOk::FinishedWithNoAdditionalRecord { .. })) => {
+                                    node.transfers.providers_done(&mut swarm.behaviour_mut().transfer, id)
+                                }
+                                kad::QueryResult::GetProviders(Err(err)) => {
+                                    eprintln!("Failed to get providers: {err:?}");
+                                    node.transfers.providers_done(&mut swarm.behaviour_mut().transfer, id)
+                                }
+                                kad::QueryResult::StartProviding(Err(err)) => {
+                                    eprintln!("Failed to announce file: {err:?}");
+                                    None
+                                }
+                                _ => None,
+                            };
+                            if let Some((key, outcome)) = finished {
+                                finish_download(swarm.behaviour_mut(), &mut node, &key, outcome);
+                            }
+                        }
+                        SwarmEvent::Behaviour(BehaviourEvent::Kademlia(kad::Event::InboundRequest { request })) => {
+                            accept_record(swarm.behaviour_mut(), &mut node, request);
+                        }
+                        // over its rate, the request goes unanswered and the peer sees it fail
+                        SwarmEvent::Behaviour(BehaviourEvent::Transfer(request_response::Event::Message {
+                            peer,
+                            message: request_response::Message::Request { .. },
+                            ..
+                        })) if !node.request_limit.allow(peer) => {}
+                        SwarmEvent::Behaviour(BehaviourEvent::Transfer(request_response::Event::Message { peer, message, .. })) => {
+                            match message {
+                                request_response::Message::Request { request: FileRequest::Replicate { key }, channel, .. } => {
+                                    let response = replicate(swarm.behaviour_mut(), &mut node, peer, &key);
+                                    let _ = swarm.behaviour_mut().transfer.send_response(channel, response);
+                                }
+                                request_response::Message::Request { request, channel, .. } => {
+                                    if let FileRequest::Manifest { key } = &request {
+                                        node.cache.touch(key);
+                                    }
+                                    let response = node.storage.respond(request);
+                                    if let FileResponse::Chunk(data) = &response {
+                                        node.metrics.served(data.len());
+                                    }
+                                    if swarm.behaviour_mut().transfer.send_response(channel, response).is_err() {
+                                        eprintln!("Failed to answer {peer}: connection closed");
+                                    }
+                                }
+                                request_response::Message::Response { request_id, response } => {
+                                    if let FileResponse::Chunk(data) = &response {
+                                        node.metrics.received(data.len());
+                                    }
+                                    let finished = node.transfers.on_response(
+                                        &mut swarm.behaviour_mut().transfer,
+                                        &node.storage,
+                                        &mut node.reputation,
+                                        request_id,
+                                        response,
+                                    );
+                                    block_banned(swarm.behaviour_mut(), &mut node);
+                                    if let Some((key, outcome)) = finished {
+                                        finish_download(swarm.behaviour_mut(), &mut node, &key, outcome);
+                                    }
+                                }
+                            }
+                        }
+                        SwarmEvent::Behaviour(BehaviourEvent::Transfer(request_response::Event::OutboundFailure { peer, request_id, error, .. })) => {
+                            eprintln!("Request to {peer} failed: {error}");
+                            if let Some((key, outcome)) = node.transfers.on_failure(&mut swarm.behaviour_mut().transfer, request_id) {
+                                finish_download(swarm.behaviour_mut(), &mut node, &key, outcome);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    };
+    Ok((handle, run))
+}
+
+fn handle_input_line(behaviour: &mut Behaviour, node: &mut Node, line: String) {
+    let mut args = line.split_whitespace();
+    let command = args.next();
+
+    // --passphrase and --to can follow the positional arguments
+    let mut positional = Vec::new();
+    let mut passphrase = None;
+    let mut recipient = None;
+    while let Some(arg) = args.next() {
+        match arg {
+            "--passphrase" => passphrase = args.next().map(String::from),
+            "--to" => recipient = args.next().map(String::from),
+            _ => positional.push(arg),
+        }
+    }
+
+    match command {
+        Some("PUT_FILE") => match positional.first() {
+            Some(file_path) => {
+                let path = PathBuf::from(file_path);
+                let key = positional.get(1).map(|key| key.to_string());
+                start_put(node, PutReply::Cli, move |writer| put_path(writer, &path, key, passphrase, recipient));
+            }
+            None => eprintln!("Usage: PUT_FILE <file_path> [optional_key] [--passphrase <p> | --to <public_key>]"),
+        },
+        Some("GET_FILE") => match positional.first() {
+            Some(arg) => match get_key(behaviour, node, arg, passphrase) {
+                Ok((key, got)) => {
+                    println!("http://{}/file/{}", node.http_addr, key);
+                    match got {
+                        Got::Saved(path) => println!("File retrieved from sled and saved as: {}", path.display()),
+                        Got::Running => println!("Download of {key} already running"),
+                        Got::Restoring | Got::Started => {}
+                    }
+                }
+                Err(e) => eprintln!("{e}"),
+            },
+            None => eprintln!("Usage: GET_FILE <key | capability> [--passphrase <p>]"),
+        },
+        Some("LIST_FILE") => {
+            println!("Stored files:");
+            for (key, file) in stored_files(node) {
+                let pinned = if node.cache.is_pinned(&key) { " | pinned" } else { "" };
+                println!("Key: {} | File Name: {} | {} bytes | {}{}", key, file.name, file.size, file.mime, pinned);
+            }
+        }
+        Some("LIST_PEERS") => {
+            let peers = node.peers.list();
+            if peers.is_empty() {
+                println!("No peers known.");
+            }
+            for peer in peers {
+                println!("{peer}");
+            }
+        }
+        Some("STATUS") => {
+            let factor = node.replication.factor;
+            println!("Replication factor: {factor}");
+            let mut under = 0;
+            for key in node.storage.file_keys() {
+                match node.replication.live(&key) {
+                    Some(live) if live < factor => {
+                        under += 1;
+                        println!("{key}: {live}/{factor} providers (under-replicated)");
+                    }
+                    Some(live) => println!("{key}: {live}/{factor} providers"),
+                    None => println!("{key}: not checked yet"),
+                }
+            }
+            println!("{under} under-replicated file(s)");
+            match node.quota {
+                Some(quota) => println!("Storage: {} of {} bytes used", node.storage.stored_bytes(), quota),
+                None => println!("Storage: {} bytes used, no quota", node.storage.stored_bytes()),
+            }
+        }
+        Some("MY_KEY") => {
+            let public = x25519_dalek::PublicKey::from(&node.secret);
+            println!("Public key: {}", crypto::encode_public(&public));
+            println!("Others can store files only this node can read with: PUT_FILE <file_path> --to <public_key>");
+        }
+        Some("PIN") => match positional.first() {
+            Some(key) => match pin_file(node, key) {
+                Ok(()) => println!("Pinned {key}"),
+                Err(e) => eprintln!("{e}"),
+            },
+            None => eprintln!("Usage: PIN <key>"),
+        },
+        Some("UNPIN") => match positional.first() {
+            Some(key) => match unpin_file(behaviour, node, key) {
+                Ok(true) => println!("Unpinned {key}"),
+                Ok(false) => println!("{key} was not pinned"),
+                Err(e) => eprintln!("{e}"),
+            },
+            None => eprintln!("Usage: UNPIN <key>"),
+        },
+        Some("GC") => collect_garbage(behaviour, node),
+        Some("DOWNLOADS") => {
+            let downloads = node.transfers.progress(&node.storage);
+            if downloads.is_empty() {
+                println!("No downloads running or paused");
+            }
+            for progress in downloads {
+                println!("{progress}");
+            }
+        }
+        Some("CANCEL") => match positional.first() {
+            Some(key) => {
+                if !cancel_download(node, key) {
+                    println!("No download of {key} to cancel");
+                }
+            }
+            None => eprintln!("Usage: CANCEL <key>"),
+        },
+        Some("BAN") => match positional.first().map(|peer| peer.parse::<PeerId>()) {
+            Some(Ok(peer)) => {
+                let reason = match positional[1..].join(" ") {
+                    reason if reason.is_empty() => "banned by hand".to_string(),
+                    reason => reason,
+                };
+                if let Err(e) = node.reputation.ban(peer, &reason) {
+                    eprintln!("Failed to ban {peer}: {e}");
+                }
+                block_banned(behaviour, node);
+            }
+            Some(Err(e)) => eprintln!("Invalid peer ID: {e}"),
+            None => eprintln!("Usage: BAN <peer_id> [reason]"),
+        },
+        Some("UNBAN") => match positional.first().map(|peer| peer.parse::<PeerId>()) {
+            Some(Ok(peer)) => match node.reputation.unban(&peer) {
+                Ok(true) => {
+                    behaviour.banned.unblock_peer(peer);
+                    println!("Unbanned {peer}");
+                }
+                Ok(false) => println!("{peer} was not banned"),
+                Err(e) => eprintln!("Failed to unban {peer}: {e}"),
+            },
+            Some(Err(e)) => eprintln!("Invalid peer ID: {e}"),
+            None => eprintln!("Usage: UNBAN <peer_id>"),
+        },
+        Some("BANS") => {
+            let bans = node.reputation.bans();
+            if bans.is_empty() {
+                println!("No banned peers");
+            }
+            for (peer, reason, since) in bans {
+                println!("{peer} | {reason} | since {since} | score {}", node.reputation.score(&peer));
+            }
+        }
+        _ => eprintln!("Invalid command."),
+    }
+}
+
+// records other peers put, stored unless the peer is over its rate, the record over the size
+// limits or the peer already has MAX_RECORDS_PER_PEER stored here
+fn accept_record(behaviour: &mut Behaviour, node: &mut Node, request: kad::InboundRequest) {
+    match request {
+        kad::InboundRequest::PutRecord { source, record: Some(mut record), .. } => {
+            if !node.put_limit.allow(source) {
+                return;
+            }
+            let store = behaviour.kademlia.store_mut();
+            // records are counted against their publisher, the sender if it didn't name one
+            let publisher = *record.publisher.get_or_insert(source);
+            let replaces = store.get(&record.key).is_some_and(|stored| stored.publisher == Some(publisher));
+            if !reputation::valid_record(&record) {
+                node.reputation.penalize(source, Offence::OversizedRecord);
+            } else if !replaces && store.held_for(&publisher) >= reputation::MAX_RECORDS_PER_PEER {
+                eprintln!("Ignored a record from {source}, {publisher} has {} stored here already", reputation::MAX_RECORDS_PER_PEER);
+            } else if let Err(e) = store.put(record) {
+                eprintln!("Failed to store a record from {source}: {e:?}");
+            }
+        }
+        kad::InboundRequest::AddProvider { record: Some(record) } => {
+            // Kademlia only passes on provider records of the peer that sent them
+            let source = record.provider;
+            if !node.put_limit.allow(source) {
+                return;
+            }
+            let store = behaviour.kademlia.store_mut();
+            let refreshes = store.providers(&record.key).iter().any(|stored| stored.provider == source);
+            if !reputation::valid_provider(&record) {
+                node.reputation.penalize(source, Offence::OversizedRecord);
+            } else if !refreshes && store.held_for(&source) >= reputation::MAX_RECORDS_PER_PEER {
+                eprintln!("Ignored a provider record from {source}, it has {} stored here already", reputation::MAX_RECORDS_PER_PEER);
+            } else if let Err(e) = store.add_provider(record) {
+                eprintln!("Failed to store a provider record from {source}: {e:?}");
+            }
+        }
+        _ => {}
+    }
+    block_banned(behaviour, node);
+}
+
+// libp2p's metrics for the swarm and each protocol that has them
+fn record_event(metrics: &Metrics, event: &SwarmEvent<BehaviourEvent>) {
+    metrics.record(event);
+    match event {
+        SwarmEvent::Behaviour(BehaviourEvent::Kademlia(event)) => metrics.record(event),
+        SwarmEvent::Behaviour(BehaviourEvent::Identify(event)) => metrics.record(event),
+        SwarmEvent::Behaviour(BehaviourEvent::Ping(event)) => metrics.record(event),
+        SwarmEvent::Behaviour(BehaviourEvent::Relay(event)) => metrics.record(event),
+        SwarmEvent::Behaviour(BehaviourEvent::Dcutr(event)) => metrics.record(event),
+        _ => {}
+    }
+}
+
+// disconnect peers banned since the last call and keep them out, including of the routing table
+fn block_banned(behaviour: &mut Behaviour, node: &mut Node) {
+    for peer in node.reputation.take_banned() {
+        behaviour.banned.block_peer(peer);
+        behaviour.kademlia.remove_peer(&peer);
+    }
+}
+
+// requests coming from the HTTP gateway
+fn handle_command(behaviour: &mut Behaviour, node: &mut Node, command: Command) {
+    match command {
+        Command::Put { key, name, path, reply } => start_put(node, PutReply::Upload(reply), move |writer| {
+            let file = fs::File::open(&path).map_err(|e| format!("Failed to read upload: {e}"))?;
+            store_file(writer, key, &name, Kind::File, file).map(Stored::File)
+        }),
+        Command::Fetch { key, reply } => {
+            // it may have arrived since the gateway looked
+            if StoredFile::load(&node.db, &key).is_some() {
+                let _ = reply.send(Ok(()));
+                return;
+            }
+            node.waiters.entry(key.clone()).or_default().push(reply);
+            if !node.transfers.is_active(&key) {
+                fetch_file(behaviour, node, &key);
+            }
+        }
+        Command::Delete { key, reply } => {
+            let _ = reply.send(delete_file(behaviour, node, &key));
+        }
+        Command::Progress { reply } => {
+            let _ = reply.send(node.transfers.progress(&node.storage));
+        }
+        Command::Cancel { key, reply } => {
+            let _ = reply.send(cancel_download(node, &key));
+        }
+        Command::Peers { reply } => {
+            let _ = reply.send(node.peers.list());
+        }
+        Command::Call { call, reply } => handle_call(behaviour, node, call, reply),
+        Command::Metrics { reply } => {
+            let keys = node.storage.file_keys();
+            let factor = node.replication.factor;
+            let store = StoreStats {
+                files: keys.len(),
+                bytes: node.storage.stored_bytes(),
+                quota: node.quota,
+                under_replicated: keys.iter().filter(|key| node.replication.live(key).is_some_and(|live| live < factor)).count(),
+            };
+            let _ = reply.send(node.metrics.encode(store));
+        }
+    }
+}
+
+// methods of the control API, answered as JSON. A get of a file that isn't here is
+// answered once its download finishes or fails
+fn handle_call(behaviour: &mut Behaviour, node: &mut Node, call: Call, reply: control::Reply) {
+    let result = match call {
+        Call::Put { path, key, passphrase, to } => {
+            start_put(node, PutReply::Call(reply), move |writer| put_path(writer, &path, key, passphrase, to));
+            return;
+        }
+        Call::Get { key, passphrase } => match get_key(behaviour, node, &key, passphrase) {
+            Ok((key, Got::Saved(path))) => Ok(describe(node, &key, Some(&path))),
+            Ok((key, Got::Restoring)) => Ok(describe(node, &key, None)),
+            Ok((key, Got::Started | Got::Running)) => {
+                node.gets.entry(key).or_default().push(reply);
+                return;
+            }
+            Err(e) => Err(e),
+        },
+        Call::List => Ok(stored_files(node).iter().map(|(key, _)| describe(node, key, None)).collect()),
+        Call::Pin { key } => pin_file(node, &key).map(|()| json!({ "key": key, "pinned": true })),
+        Call::Unpin { key } => unpin_file(behaviour, node, &key).map(|was_pinned| json!({ "key": key, "pinned": false, "was_pinned": was_pinned })),
+        Call::Peers => Ok(json!(node.peers.list())),
+        Call::Status => Ok(status(node)),
+    };
+    let _ = reply.send(result);
+}
+
+// answer the control API gets waiting for `key`
+fn answer_gets(node: &mut Node, key: &str, result: Result<Value, String>) {
+    for reply in node.gets.remove(key).unwrap_or_default() {
+        let _ = reply.send(result.clone());
+    }
+}
+
+// a stored file as the control API lists it, with where it was written if it was
+fn describe(node: &Node, key: &str, path: Option<&Path>) -> Value {
+    let Some(file) = StoredFile::load(&node.db, key) else {
+        return json!({ "key": key });
+    };
+    let mut described = json!({
+        "key": key,
+        "name": file.name,
+        "size": file.size,
+        "mime": file.mime,
+        "hash": file.hash,
+        "pinned": node.cache.is_pinned(key),
+        "directory": file.is_directory(),
+    });
+    // the caller may run in another directory
+    if let Some(path) = path {
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        described["path"] = json!(path.display().to_string());
+    }
+    described
+}
+
+fn status(node: &Node) -> Value {
+    let factor = node.replication.factor;
+    let files: Vec<Value> = node
+        .storage
+        .file_keys()
+        .into_iter()
+        .map(|key| json!({ "key": key, "providers": node.replication.live(&key) }))
+        .collect();
+    let under = files.iter().filter(|file| file["providers"].as_u64().is_some_and(|live| (live as usize) < factor)).count();
+    let reachability = match node.nat.status {
+        autonat::NatStatus::Public(_) => "public",
+        autonat::NatStatus::Private => "private",
+        autonat::NatStatus::Unknown => "unknown",
+    };
+    json!({
+        "peer_id": node.peer_id.to_string(),
+        "listen_addrs": node.listening.iter().map(|addr| addr.to_string()).collect::<Vec<_>>(),
+        "replication_factor": factor,
+        "files": files,
+        "under_replicated": under,
+        "used_bytes": node.storage.stored_bytes(),
+        "quota_bytes": node.quota,
+        "reachability": reachability,
+        "connected_peers": node.peers.list().iter().filter(|peer| peer.state == "connected").count(),
+        "downloads": node.transfers.progress(&node.storage),
+    })
+}
+
+// every file in sled, for LIST_FILE and the list call
+fn stored_files(node: &Node) -> Vec<(String, StoredFile)> {
+    let mut files = Vec::new();
+    for entry in node.db.iter() {
+        match entry {
+            Ok((key, value)) => {
+                if let Some(file) = StoredFile::from_bytes(&value) {
+                    files.push((String::from_utf8_lossy(&key).to_string(), file));
+                }
+            }
+            Err(e) => eprintln!("Error reading from DB: {e}"),
+        }
+    }
+    files
+}
+
+fn pin_file(node: &Node, key: &str) -> Result<(), String> {
+    if node.storage.manifest(key).is_none() {
+        return Err(format!("{key} is not stored here, fetch it with GET_FILE first"));
+    }
+    node.cache.pin(key).map_err(|e| format!("Failed to pin {key}: {e}"))
+}
+
+// false if the file wasn't pinned
+fn unpin_file(behaviour: &mut Behaviour, node: &mut Node, key: &str) -> Result<bool, String> {
+    let unpinned = node.cache.unpin(key).map_err(|e| format!("Failed to unpin {key}: {e}"))?;
+    if unpinned {
+        enforce_quota(behaviour, node, None);
+    }
+    Ok(unpinned)
+}
+
+// what PUT_FILE and the put call stored
+enum Stored {
+    File(String),
+    Encrypted(Capability),
+    Directory(Entry),
+}
+
+// who is waiting for a put
+enum PutReply {
+    Cli,
+    Call(control::Reply),
+    Upload(oneshot::Sender<Result<String, String>>), // POST /file, only ever stores a file
+}
+
+// a put written to sled off the swarm loop, the loop announces its keys and answers
+struct Put {
+    stored: Result<Stored, String>,
+    keys: Vec<String>,             // every file and directory node written
+    timings: Vec<(Duration, bool)>, // how long each file took and whether it was stored
+    reply: PutReply,
+}
+
+// what chunking files into sled needs, moved to the blocking pool with the put
+struct Writer {
+    db: sled::Db,
+    storage: Storage,
+    cache: Cache,
+    keys: Vec<String>,
+    timings: Vec<(Duration, bool)>,
+}
+
+// chunk and hash a put on the blocking pool, a file of up to 64 GiB would hold up the swarm
+// for its whole length. `write` does the storing, `finish_put` picks the result up
+fn start_put(node: &mut Node, reply: PutReply, write: impl FnOnce(&mut Writer) -> Result<Stored, String> + Send + 'static) {
+    let mut writer = Writer {
+        db: node.db.clone(),
+        storage: node.storage.clone(),
+        cache: node.cache.clone(),
+        keys: Vec::new(),
+        timings: Vec::new(),
+    };
+    let done = node.puts.clone();
+    node.writing += 1;
+    tokio::task::spawn_blocking(move || {
+        let stored = write(&mut writer);
+        let _ = done.send(Put { stored, keys: writer.keys, timings: writer.timings, reply });
+    });
+}
+
+// tell the DHT we provide what a put wrote, then answer whoever asked for it
+fn finish_put(behaviour: &mut Behaviour, node: &mut Node, put: Put) {
+    node.writing -= 1;
+    for (took, ok) in put.timings {
+        node.metrics.put(took, ok);
+    }
+    for key in &put.keys {
+        if let Err(e) = behaviour.kademlia.start_providing(kad::RecordKey::new(key)) {
+            eprintln!("Failed to announce {key} in DHT: {e:?}");
+        }
+    }
+    // what was just put is pinned, only other files can go
+    enforce_quota(behaviour, node, None);
+    match put.reply {
+        PutReply::Cli => match put.stored {
+            Ok(Stored::File(key)) => println!("File stored with key: {}. Retrieve via: http://{}/file/{}", key, node.http_addr, key),
+            Ok(Stored::Encrypted(capability)) => {
+                println!("Encrypted file stored with key: {}", capability.key);
+                println!("Capability (keep it secret, it decrypts the file): {capability}");
+            }
+            Ok(Stored::Directory(root)) => println!(
+                "Directory stored with key: {} ({} bytes). Browse via: http://{}/file/{}",
+                root.key, root.size, node.http_addr, root.key
+            ),
+            Err(e) => eprintln!("{e}"),
+        },
+        PutReply::Call(reply) => {
+            let _ = reply.send(put.stored.map(|stored| match stored {
+                Stored::File(key) => json!({ "key": key }),
+                Stored::Encrypted(capability) => json!({ "key": capability.key, "capability": capability.to_string() }),
+                Stored::Directory(root) => json!({ "key": root.key, "size": root.size, "directory": true }),
+            }));
+        }
+        PutReply::Upload(reply) => {
+            let _ = reply.send(put.stored.map(|stored| match stored {
+                Stored::File(key) => key,
+                Stored::Encrypted(capability) => capability.key,
+                Stored::Directory(root) => root.key,
+            }));
+        }
+    }
+}
+
+// store a file or a whole directory from disk. A file is encrypted if given a passphrase or
+// the public key of the node that may read it
+fn put_path(
+    writer: &mut Writer,
+    path: &Path,
+    key: Option<String>,
+    passphrase: Option<String>,
+    recipient: Option<String>,
+) -> Result<Stored, String> {
+    if !path.exists() {
+        return Err(format!("File does not exist: {}", path.display()));
+    }
+    if path.is_dir() {
+        if passphrase.is_some() || recipient.is_some() {
+            return Err("Directories can't be stored encrypted yet, encrypt the files one by one".to_string());
+        }
+        return store_directory(writer, path, key).map(Stored::Directory);
+    }
+    let lock = match (passphrase, recipient) {
+        (Some(passphrase), None) => Some(Lock::Passphrase(passphrase)),
+        (None, Some(recipient)) => match crypto::parse_public(&recipient) {
+            Some(public) => Some(Lock::Recipient(public)),
+            None => return Err(format!("Invalid public key: {recipient}")),
+        },
+        (None, None) => None,
+        (Some(_), Some(_)) => return Err("Use either a passphrase or a public key, not both".to_string()),
+    };
+
+    // the file is read a chunk at a time, never whole
+    let file = fs::File::open(path).map_err(|e| format!("Error reading file: {e}"))?;
+    let filename = path.file_name().unwrap().to_string_lossy();
+    let Some(lock) = lock else {
+        return store_file(writer, key, &filename, Kind::File, file).map(Stored::File);
+    };
+    // nodes only ever see the ciphertext, the real name is inside it
+    let encryptor = Encryptor::new(&filename, file, &lock).map_err(|e| format!("Failed to encrypt file: {e}"))?;
+    let file_key = encryptor.key();
+    let stored_name = format!("{:016x}.enc", rand::random::<u64>());
+    let key = store_file(writer, key, &stored_name, Kind::File, encryptor)?;
+    Ok(Stored::Encrypted(Capability { key, file_key }))
+}
+
+// what GET_FILE and the get call did with a key
+enum Got {
+    Saved(PathBuf), // held here, written to the retrieved directory
+    Restoring,      // a directory held here, written out as its files come in
+    Started,        // download started
+    Running,        // download already running
+}
+
+// write a file held here to the retrieved directory, or start downloading it. `arg` is a key
+// or a capability, which carries the key and what decrypts the file
+fn get_key(behaviour: &mut Behaviour, node: &mut Node, arg: &str, passphrase: Option<String>) -> Result<(String, Got), String> {
+    let (key, unlock) = match Capability::parse(arg) {
+        Some(capability) => (capability.key, Some(Unlock::Key(capability.file_key))),
+        None => (arg.to_string(), passphrase.map(Unlock::Passphrase)),
+    };
+    if let Some(file) = StoredFile::load(&node.db, &key)
+        && let Some(manifest) = node.storage.manifest(&key)
+    {
+        node.cache.touch(&key);
+        if file.is_directory() {
+            restore_directory(behaviour, node, &key);
+            return Ok((key, Got::Restoring));
+        }
+        let path = write_retrieved(node, &key, &manifest, unlock).map_err(|e| format!("Failed to save file: {e}"))?;
+        return Ok((key, Got::Saved(path)));
+    }
+    if let Some(unlock) = unlock {
+        node.unlocks.insert(key.clone(), unlock);
+    }
+    node.wanted.insert(key.clone());
+    if node.transfers.is_active(&key) {
+        return Ok((key, Got::Running));
+    }
+    fetch_file(behaviour, node, &key);
+    Ok((key, Got::Started))
+}
+
+// split a file into chunks as it's read and keep it in sled, the swarm loop tells the DHT
+// we provide it. Returns the key, the sha256 of the content unless one is given
+fn store_file(writer: &mut Writer, key: Option<String>, filename: &str, kind: Kind, content: impl Read) -> Result<String, String> {
+    let started = Instant::now();
+    let stored = chunk_file(writer, key, filename, kind, content);
+    writer.timings.push((started.elapsed(), stored.is_ok()));
+    if let Ok(key) = &stored {
+        writer.keys.push(key.clone());
+    }
+    stored
+}
+
+fn chunk_file(
+    writer: &Writer,
+    key: Option<String>,
+    filename: &str,
+    kind: Kind,
+    content: impl Read,
+) -> Result<String, String> {
+    let mut new_chunks = 0;
+    let (mut manifest, hash) = chunk::split(filename, content, |hash, data| {
+        if writer.storage.put_chunk(hash, data).map_err(std::io::Error::other)? {
+            new_chunks += 1;
+        }
+        Ok(())
+    })
+    .map_err(|e| format!("Failed to store file: {e}"))?;
+    // downloads check content against keys of this form, a file stored under a different one could never be fetched
+    if let Some(key) = &key
+        && chunk::is_content_hash(key)
+        && *key != hash
+    {
+        return Err(format!("{key} looks like a sha256 but isn't the file's, choose another key"));
+    }
+    let key = key.unwrap_or_else(|| hash.clone());
+    manifest.kind = kind;
+
+    writer
+        .storage
+        .commit(&key, &manifest)
+        .map_err(|e| format!("Failed to store chunks in sled: {e}"))?;
+    let file = StoredFile::new(filename, manifest.size, hash, kind);
+    file.save(&writer.db, &key).map_err(|e| format!("Failed to store file in sled: {e}"))?;
+    println!("{} chunks, {} already stored", manifest.chunks.len(), manifest.chunks.len() - new_chunks);
+    // files put on this node are its own, only copies fetched from others get evicted
+    writer.cache.record(&key, file.size);
+    if let Err(e) = writer.cache.pin(&key) {
+        eprintln!("Failed to pin {key}: {e}");
+    }
+    Ok(key)
+}
+
+// store every file below `path` and then the directory nodes, bottom up. Returns the entry of
+// `path` itself, stored under `key` if one is given and under the hash of its node otherwise
+fn store_directory(writer: &mut Writer, path: &Path, key: Option<String>) -> Result<Entry, String> {
+    let name = path
+        .canonicalize()
+        .ok()
+        .and_then(|path| path.file_name().map(|name| name.to_string_lossy().to_string()))
+        .unwrap_or_else(|| "root".to_string());
+    let listing = fs::read_dir(path).map_err(|e| format!("Error reading {}: {e}", path.display()))?;
+
+    let mut entries = Vec::new();
+    for item in listing {
+        let item = item.map_err(|e| format!("Error reading {}: {e}", path.display()))?;
+        let file_type = item.file_type().map_err(|e| format!("Error reading {}: {e}", item.path().display()))?;
+        let item_name = item.file_name().to_string_lossy().to_string();
+        if file_type.is_dir() {
+            entries.push(store_directory(writer, &item.path(), None)?);
+        } else if file_type.is_file() {
+            let file = fs::File::open(item.path()).map_err(|e| format!("Error reading {}: {e}", item.path().display()))?;
+            let key = store_file(writer, None, &item_name, Kind::File, file)?;
+            let size = StoredFile::load(&writer.db, &key).map_or(0, |file| file.size);
+            entries.push(Entry { name: item_name, kind: Kind::File, key, size });
+        }
+        // symlinks and special files are left out
+    }
+
+    let directory = Directory::new(&name, entries);
+    let size = directory.size();
+    let key = store_file(writer, key, &name, Kind::Directory, directory.to_bytes().as_slice())?;
+    Ok(Entry { name, kind: Kind::Directory, key, size })
+}
+
+// keys below a directory that aren't stored here yet, the directory itself included. Entries
+// have to be named by the hash of their content, downloads check it, so nothing below the root
+// can be swapped out
+fn missing_below(node: &Node, root: &str) -> Result<HashSet<String>, String> {
+    let mut missing = HashSet::new();
+    let mut seen = HashSet::new();
+    let mut stack = vec![root.to_string()];
+    while let Some(key) = stack.pop() {
+        // the same subtree can appear under several names, it is walked once
+        if !seen.insert(key.clone()) {
+            continue;
+        }
+        if !node.db.contains_key(&key).unwrap_or(false) {
+            missing.insert(key);
+            continue;
+        }
+        for entry in load_directory(node, &key).map(|dir| dir.entries).unwrap_or_default() {
+            if !chunk::is_content_hash(&entry.key) {
+                return Err(format!("entry {} of {key} isn't named by its content hash", entry.name));
+            }
+            match entry.kind {
+                Kind::Directory => stack.push(entry.key),
+                Kind::File if !node.db.contains_key(&entry.key).unwrap_or(false) => {
+                    missing.insert(entry.key);
+                }
+                Kind::File => {}
+            }
+        }
+    }
+    Ok(missing)
+}
+
+// fetch what is still missing below a directory, or write the tree to the retrieved
+// directory once everything is stored here
+fn restore_directory(behaviour: &mut Behaviour, node: &mut Node, root: &str) {
+    let missing = match missing_below(node, root) {
+        Ok(missing) => missing,
+        Err(e) => {
+            eprintln!("Failed to restore directory {root}: {e}");
+            node.restores.remove(root);
+            return;
+        }
+    };
+    if missing.is_empty() {
+        node.restores.remove(root);
+        match write_directory(node, root, &node.retrieved_dir, 0) {
+            Ok(path) => {
+                node.cache.add_retrieved(&path, root);
+                println!("Directory retrieved and saved as: {}", path.display());
+            }
+            Err(e) => eprintln!("Failed to save directory {root}: {e}"),
+        }
+        return;
+    }
+    for key in &missing {
+        if !node.transfers.is_active(key) {
+            fetch_file(behaviour, node, key);
+        }
+    }
+    if node.restores.insert(root.to_string(), missing).is_none() {
+        println!("Fetching the missing entries of directory {root}");
+    }
+}
+
+// recreate a stored directory below `parent`, returns the path it was written to
+fn write_directory(node: &Node, key: &str, parent: &Path, depth: usize) -> std::io::Result<PathBuf> {
+    let invalid = |reason: String| std::io::Error::new(std::io::ErrorKind::InvalidData, reason);
+    if depth > directory::MAX_DEPTH {
+        return Err(invalid(format!("directories nested deeper than {}", directory::MAX_DEPTH)));
+    }
+    let directory = load_directory(node, key).ok_or_else(|| invalid(format!("{key} is not a stored directory")))?;
+    let path = parent.join(safe_name(&directory.name, key));
+    fs::create_dir_all(&path)?;
+    for entry in &directory.entries {
+        if !chunk::is_content_hash(&entry.key) {
+            return Err(invalid(format!("entry {} of {key} isn't named by its content hash", entry.name)));
+        }
+        match entry.kind {
+            Kind::Directory => {
+                write_directory(node, &entry.key, &path, depth + 1)?;
+            }
+            Kind::File => copy_out(node, &entry.key, &path.join(safe_name(&entry.name, &entry.key)))?,
+        }
+    }
+    Ok(path)
+}
+
+// a stored directory node, None for files and keys not stored here
+fn load_directory(node: &Node, key: &str) -> Option<Directory> {
+    if !StoredFile::load(&node.db, key)?.is_directory() {
+        return None;
+    }
+    let mut bytes = Vec::new();
+    node.storage.reader(&node.storage.manifest(key)?, None).read_to_end(&mut bytes).ok()?;
+    Directory::from_bytes(&bytes)
+}
+
+// stream a stored file from sled to `path`
+fn copy_out(node: &Node, key: &str, path: &Path) -> std::io::Result<()> {
+    let manifest = node
+        .storage
+        .manifest(key)
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, format!("{key} is not stored")))?;
+    let mut out = BufWriter::new(fs::File::create(path)?);
+    std::io::copy(&mut node.storage.reader(&manifest, None), &mut out)?;
+    out.flush()
+}
+
+// start the downloads that stopped, after a restart or because their providers went away
+fn resume_downloads(behaviour: &mut Behaviour, node: &mut Node) {
+    for key in node.transfers.resumable() {
+        fetch_file(behaviour, node, &key);
+    }
+}
+
+// the chunks it fetched stay until the next GC
+fn cancel_download(node: &mut Node, key: &str) -> bool {
+    let cancelled = node.transfers.cancel(key);
+    if cancelled {
+        fail_download(node, key, "download cancelled".to_string());
+    }
+    cancelled
+}
+
+// find who holds the file, chunks are then pulled from them
+fn fetch_file(behaviour: &mut Behaviour, node: &mut Node, key: &str) {
+    let query = behaviour.kademlia.get_providers(kad::RecordKey::new(&key));
+    node.transfers.start(&node.storage, key, query);
+    node.metrics.get_started(key);
+}
+
+// take a copy of a file another peer asked us to keep. Only peers in good standing, or already
+// providing the file with us, are listened to, and never once the quota is used up. A refusal
+// is answered NotFound
+fn replicate(behaviour: &mut Behaviour, node: &mut Node, peer: PeerId, key: &str) -> FileResponse {
+    if node.storage.manifest(key).is_some() || node.transfers.is_active(key) {
+        return FileResponse::Accepted;
+    }
+    let sharing = behaviour
+        .kademlia
+        .store_mut()
+        .providers(&kad::RecordKey::new(&key))
+        .iter()
+        .any(|record| record.provider == peer);
+    if node.reputation.is_banned(&peer) || (node.reputation.score(&peer) < 0 && !sharing) {
+        eprintln!("Refused to replicate {key} for {peer}, score {}", node.reputation.score(&peer));
+        return FileResponse::NotFound;
+    }
+    if let Some(quota) = node.quota.filter(|quota| node.storage.stored_bytes() >= *quota) {
+        eprintln!("Refused to replicate {key} for {peer}, the {quota} byte quota is used up");
+        return FileResponse::NotFound;
+    }
+    println!("{peer} asked us to keep a copy of {key}");
+    fetch_file(behaviour, node, key);
+    FileResponse::Accepted
+}
+
+// start counting the providers of every file we hold
+fn check_replication(behaviour: &mut Behaviour, node: &mut Node) {
+    for key in node.storage.file_keys() {
+        if !node.replication.is_checking(&key) {
+            let query = behaviour.kademlia.get_providers(kad::RecordKey::new(&key));
+            node.replication.start(&key, query);
+        }
+    }
+}
+
+// listen through the known relays while we need them, see Nat::wanted. Relays are connected
+// first, the reservation's own dial is refused while another one to the relay is running
+fn reserve_relays(swarm: &mut Swarm<Behaviour>, node: &mut Node) {
+    for (relay, addr) in node.nat.wanted() {
+        if !swarm.is_connected(&relay) {
+            // ConnectionEstablished calls us again, nothing to do while a dial is running
+            let _ = swarm.dial(DialOpts::peer_id(relay).addresses(vec![addr]).build());
+            continue;
+        }
+        let circuit = nat::circuit(&addr, relay);
+        match swarm.listen_on(circuit.clone()) {
+            Ok(listener) => node.nat.reserved(relay, listener),
+            Err(e) => eprintln!("Failed to listen through relay {circuit}: {e}"),
+        }
+    }
+}
+
+// count the providers that are still around and ask other peers to take a copy if there are too few
+fn repair_file(swarm: &mut Swarm<Behaviour>, node: &mut Node, key: &str, providers: HashSet<PeerId>) {
+    let local = *swarm.local_peer_id();
+    // provider records outlive their peers, only count the ones we can still see
+    let others = providers
+        .iter()
+        .filter(|peer| **peer != local && node.peers.is_live(peer))
+        .count();
+    let live = others + 1; // we hold the file
+    node.replication.record(key, live);
+
+    let factor = node.replication.factor;
+    if live >= factor {
+        return;
+    }
+    let candidates: HashSet<PeerId> = node
+        .peers
+        .live()
+        .filter(|peer| **peer != local && !providers.contains(peer))
+        .copied()
+        .collect();
+    if candidates.is_empty() {
+        eprintln!("{key} is under-replicated ({live}/{factor}) and no other peer can take a copy");
+        return;
+    }
+    for peer in candidates.into_iter().take(factor - live) {
+        println!("Asking {peer} to replicate {key} ({live}/{factor} providers)");
+        swarm.behaviour_mut().transfer.send_request(&peer, FileRequest::Replicate { key: key.to_string() });
+    }
+}
+
+// drop a file and its chunks and stop announcing it, false if we didn't have it
+fn delete_file(behaviour: &mut Behaviour, node: &mut Node, key: &str) -> bool {
+    let had_file = matches!(node.db.remove(key), Ok(Some(_)));
+    let had_chunks = match node.storage.remove_file(key) {
+        Ok(removed) => removed,
+        Err(e) => {
+            eprintln!("Failed to remove chunks of {key}: {e}");
+            false
+        }
+    };
+    behaviour.kademlia.stop_providing(&kad::RecordKey::new(&key));
+    node.replication.forget(key);
+    node.cache.forget(key);
+    had_file || had_chunks
+}
+
+// evict unpinned files, least recently used first, until the stored chunks fit in the quota
+fn enforce_quota(behaviour: &mut Behaviour, node: &mut Node, keep: Option<&str>) {
+    let Some(quota) = node.quota else {
+        return;
+    };
+    for key in node.cache.eviction_order(keep) {
+        if node.storage.stored_bytes() <= quota {
+            return;
+        }
+        if delete_file(behaviour, node, &key) {
+            println!("Evicted {key} to stay within the {quota} byte quota");
+        }
+    }
+    let used = node.storage.stored_bytes();
+    if used > quota {
+        eprintln!("Stored chunks use {used} bytes, over the {quota} byte quota, with nothing left to evict");
+    }
+}
+
+// bring the sled trees back in line after interrupted writes and drop retrieved copies of
+// files that are gone
+fn collect_garbage(behaviour: &mut Behaviour, node: &mut Node) {
+    // chunks of a put are stored before the manifest that references them
+    if node.writing > 0 {
+        println!("{} put(s) still being written, run GC once they are done", node.writing);
+        return;
+    }
+    // a file whose manifest was never committed has no content to serve, drop the record
+    let mut incomplete = 0;
+    for key in node.db.iter().keys().filter_map(|key| key.ok()) {
+        let key = String::from_utf8_lossy(&key).to_string();
+        if node.storage.manifest(&key).is_none() && node.db.remove(&key).is_ok() {
+            node.cache.forget(&key);
+            incomplete += 1;
+        }
+    }
+
+    // chunks left of a file that was being deleted
+    let mut dropped = 0;
+    for key in node.storage.file_keys() {
+        if !node.db.contains_key(&key).unwrap_or(true) {
+            delete_file(behaviour, node, &key);
+            dropped += 1;
+        }
+    }
+    let (chunks, chunk_bytes) = node.storage.collect_garbage(&node.transfers.pending_chunks()).unwrap_or_else(|e| {
+        eprintln!("Failed to collect unused chunks: {e}");
+        (0, 0)
+    });
+    let db = node.db.clone();
+    let stored = |key: &str| db.contains_key(key).unwrap_or(true);
+    let entries = node.cache.retain(stored);
+
+    let mut copies = 0;
+    for path in node.cache.take_stale_retrieved(stored) {
+        let removed = if path.is_dir() { fs::remove_dir_all(&path) } else { fs::remove_file(&path) };
+        match removed {
+            Ok(()) => copies += 1,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => eprintln!("Failed to remove {}: {e}", path.display()),
+        }
+    }
+
+    println!("Dropped {incomplete} incomplete file(s), {dropped} orphaned manifest(s) and {entries} stale pin/usage entries");
+    println!("Removed {chunks} unused chunk(s) ({chunk_bytes} bytes) and {copies} stale retrieved copies");
+    enforce_quota(behaviour, node, None);
+}
+
+// a download or a file it needed failed, so does every directory restore waiting on it.
+// A download that got its manifest is only paused, it resumes later
+fn fail_download(node: &mut Node, key: &str, reason: String) {
+    node.metrics.get_finished(key, false);
+    if node.transfers.has_session(key) {
+        if node.transfers.pause(key, &reason) {
+            println!("Download of {key} paused: {reason}. It resumes when a provider is back, CANCEL {key} to give up");
+        }
+    } else {
+        eprintln!("Download of {key} failed: {reason}");
+        node.unlocks.remove(key);
+        node.wanted.remove(key);
+    }
+    node.restores.retain(|root, missing| {
+        let failed = missing.contains(key);
+        if failed {
+            eprintln!("Download of directory {root} failed, {key} is missing");
+        }
+        !failed
+    });
+    answer_gets(node, key, Err(reason.clone()));
+    gateway::notify(&mut node.waiters, key, Err(reason));
+}
+
+// keep a fully downloaded file in sled and start providing it, then answer the HTTP requests
+// waiting for it. Only files a user asked for are written to disk, replicas and files the
+// gateway fetched stay in sled
+fn finish_download(behaviour: &mut Behaviour, node: &mut Node, key: &str, outcome: Outcome) {
+    let (download, source) = match outcome {
+        Outcome::Complete(download, source) => (download, source),
+        Outcome::Failed(reason) => return fail_download(node, key, reason),
+    };
+    // the chunks only match the manifest, a content-addressed key has to match the content too
+    let digest = node.storage.digest(&download.manifest);
+    if let Ok((hash, _)) = &digest
+        && chunk::is_content_hash(key)
+        && hash != key
+    {
+        node.transfers.end(key);
+        return reject_manifest(behaviour, node, key, source);
+    }
+    // chunks that don't add up can't be fixed by fetching the rest again
+    let wanted = node.wanted.remove(key);
+    let saved = digest.and_then(|digest| save_download(node, key, download, digest, wanted));
+    node.transfers.end(key);
+    let (file, path) = match saved {
+        Ok(saved) => saved,
+        Err(reason) => return fail_download(node, key, reason),
+    };
+    node.metrics.get_finished(key, true);
+    node.cache.record(key, file.size);
+    if let Err(e) = behaviour.kademlia.start_providing(kad::RecordKey::new(&key)) {
+        eprintln!("Failed to announce file in DHT: {e:?}");
+    }
+    gateway::notify(&mut node.waiters, key, Ok(()));
+    let described = describe(node, key, path.as_deref());
+    answer_gets(node, key, Ok(described));
+
+    // a directory pulls in everything below it, entries of one already being fetched move it along
+    let roots: Vec<String> = node
+        .restores
+        .iter()
+        .filter(|(_, missing)| missing.contains(key))
+        .map(|(root, _)| root.clone())
+        .collect();
+    if roots.is_empty() && wanted && file.is_directory() {
+        restore_directory(behaviour, node, key);
+    }
+    for root in roots {
+        restore_directory(behaviour, node, &root);
+    }
+    enforce_quota(behaviour, node, Some(key));
+}
+
+// a provider passed off other content under the key, its chunks are left to GC and the file
+// is fetched again without it
+fn reject_manifest(behaviour: &mut Behaviour, node: &mut Node, key: &str, source: Option<PeerId>) {
+    eprintln!("Content downloaded for {key} doesn't hash to the key, fetching it again");
+    if let Some(peer) = source {
+        node.reputation.penalize(peer, Offence::InvalidManifest);
+        node.transfers.distrust(key, peer);
+    }
+    fetch_file(behaviour, node, key);
+}
+
+// the chunks of a download are already in sled and add up, `digest` is their sha256 and
+// lengths. Commit the manifest, then write the file to disk if it's `wanted`. Directories and
+// the files in them are written out by `restore_directory` once complete
+fn save_download(
+    node: &mut Node,
+    key: &str,
+    download: Download,
+    (hash, lengths): (String, Vec<u32>),
+    wanted: bool,
+) -> Result<(StoredFile, Option<PathBuf>), String> {
+    let mut manifest = download.manifest;
+    manifest.lengths = lengths;
+    node.storage
+        .commit(key, &manifest)
+        .map_err(|e| format!("Failed to store chunks in sled: {e}"))?;
+
+    let file = StoredFile::new(&manifest.filename, manifest.size, hash, manifest.kind);
+    if let Err(e) = file.save(&node.db, key) {
+        eprintln!("Failed to save file to sled: {e}");
+    } else {
+        println!("File saved to sled: {key}");
+    }
+
+    let unlock = node.unlocks.remove(key);
+    let in_directory = node.restores.values().any(|missing| missing.contains(key));
+    let mut path = None;
+    if wanted && !in_directory && !file.is_directory() {
+        match write_retrieved(node, key, &manifest, unlock) {
+            Ok(written) => {
+                println!("File retrieved and saved as: {} ({} bytes)", written.display(), file.size);
+                path = Some(written);
+            }
+            Err(e) => eprintln!("Failed to save file: {e}"),
+        }
+    }
+    Ok((file, path))
+}
+
+// the file content goes to the retrieved directory under its own name, streamed from sled.
+// Encrypted files are decrypted with `unlock`, or this node's key if none was given, and kept
+// as ciphertext otherwise
+fn write_retrieved(
+    node: &Node,
+    key: &str,
+    manifest: &Manifest,
+    unlock: Option<Unlock>,
+) -> std::io::Result<PathBuf> {
+    fs::create_dir_all(&node.retrieved_dir)?;
+    let head = manifest.chunks.first().and_then(|hash| node.storage.chunk(hash)).unwrap_or_default();
+    if crypto::is_encrypted(&head) {
+        let unlock = unlock.unwrap_or_else(|| Unlock::Secret(node.secret.clone()));
+        let mut written = None;
+        let decrypted = crypto::decrypt_stream(node.storage.reader(manifest, None), &unlock, |name| {
+            let path = node.retrieved_dir.join(safe_name(name, key));
+            let file = fs::File::create(&path)?;
+            written = Some(path);
+            Ok(BufWriter::new(file))
+        });
+        match (decrypted, written) {
+            (Ok(_), Some(path)) => {
+                node.cache.add_retrieved(&path, key);
+                return Ok(path);
+            }
+            // the key was right but the content wasn't, don't leave half a file behind
+            (Err(e), Some(path)) => {
+                let _ = fs::remove_file(&path);
+                return Err(std::io::Error::other(e));
+            }
+            (Err(e), None) => eprintln!("File stays encrypted: {e}"),
+            (Ok(_), None) => unreachable!("decrypted content is always written"),
+        }
+    }
+    let path = node.retrieved_dir.join(safe_name(&manifest.filename, key));
+    copy_out(node, key, &path)?;
+    node.cache.add_retrieved(&path, key);
+    Ok(path)
+}
+
+// names come from other peers, never let one point outside the directory it's written to
+fn safe_name(name: &str, key: &str) -> String {
+    match Path::new(name).file_name() {
+        Some(name) => name.to_string_lossy().to_string(),
+        None => key.to_string(),
+    }
+}
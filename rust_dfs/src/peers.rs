@@ -0,0 +1,186 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use libp2p::{identify, multiaddr::Protocol, swarm::ConnectionId, Multiaddr, PeerId};
+use serde::Serialize;
+
+// disconnected peers are forgotten after this long without news of them
+const FORGET_AFTER: Duration = Duration::from_secs(60 * 60);
+
+// what we know of one peer
+struct Known {
+    addrs: BTreeSet<Multiaddr>,                    // from mDNS, identify and the connections we had
+    local: HashSet<Multiaddr>,                     // mDNS addresses not expired yet
+    connections: HashMap<ConnectionId, Multiaddr>, // open connections, with the peer's address
+    latency: Option<Duration>,                     // last ping round trip
+    agent: Option<String>,
+    protocols: Vec<String>,
+    last_seen: SystemTime,
+}
+
+impl Known {
+    fn new() -> Self {
+        Known {
+            addrs: BTreeSet::new(),
+            local: HashSet::new(),
+            connections: HashMap::new(),
+            latency: None,
+            agent: None,
+            protocols: Vec::new(),
+            last_seen: SystemTime::now(),
+        }
+    }
+
+    fn state(&self) -> &'static str {
+        if !self.connections.is_empty() {
+            "connected"
+        } else if !self.local.is_empty() {
+            "discovered"
+        } else {
+            "disconnected"
+        }
+    }
+}
+
+/// A peer as LIST_PEERS and `GET /peers` show it.
+#[derive(Serialize)]
+pub struct PeerInfo {
+    pub peer_id: String,
+    pub state: &'static str, // connected, discovered (seen on mDNS, no connection) or disconnected
+    pub connections: usize,
+    pub addrs: Vec<String>,
+    pub latency_ms: Option<u64>,
+    pub agent: Option<String>,
+    pub protocols: Vec<String>,
+    pub last_seen: u64, // unix seconds
+}
+
+impl fmt::Display for PeerInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} | {}", self.peer_id, self.state)?;
+        if self.connections > 1 {
+            write!(f, " ({} connections)", self.connections)?;
+        }
+        if let Some(latency) = self.latency_ms {
+            write!(f, " | {latency} ms")?;
+        }
+        if let Some(agent) = &self.agent {
+            write!(f, " | {agent}")?;
+        }
+        let ago = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            .saturating_sub(self.last_seen);
+        write!(f, " | seen {ago}s ago")?;
+        for addr in &self.addrs {
+            write!(f, "\n  {addr}")?;
+        }
+        if !self.protocols.is_empty() {
+            write!(f, "\n  protocols: {}", self.protocols.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+// the peers we are connected to or found, fed by the swarm events
+#[derive(Default)]
+pub struct Peers {
+    known: HashMap<PeerId, Known>,
+}
+
+impl Peers {
+    fn seen(&mut self, peer: PeerId) -> &mut Known {
+        let known = self.known.entry(peer).or_insert_with(Known::new);
+        known.last_seen = SystemTime::now();
+        known
+    }
+
+    pub fn discovered(&mut self, peer: PeerId, addr: Multiaddr) {
+        let known = self.seen(peer);
+        known.addrs.insert(without_peer(&addr));
+        known.local.insert(addr);
+    }
+
+    pub fn expired(&mut self, peer: PeerId, addr: &Multiaddr) {
+        if let Some(known) = self.known.get_mut(&peer) {
+            known.local.remove(addr);
+        }
+    }
+
+    pub fn connected(&mut self, peer: PeerId, connection: ConnectionId, addr: Multiaddr) {
+        let known = self.seen(peer);
+        known.addrs.insert(without_peer(&addr));
+        known.connections.insert(connection, addr);
+    }
+
+    pub fn disconnected(&mut self, peer: PeerId, connection: ConnectionId) {
+        let known = self.seen(peer);
+        known.connections.remove(&connection);
+        if known.connections.is_empty() {
+            known.latency = None;
+        }
+    }
+
+    pub fn pinged(&mut self, peer: PeerId, rtt: Duration) {
+        self.seen(peer).latency = Some(rtt);
+    }
+
+    pub fn identified(&mut self, peer: PeerId, info: &identify::Info) {
+        let known = self.seen(peer);
+        known.addrs.extend(info.listen_addrs.iter().map(without_peer));
+        known.agent = Some(info.agent_version.clone());
+        known.protocols = info.protocols.iter().map(|protocol| protocol.to_string()).collect();
+        known.protocols.sort();
+    }
+
+    // connected, or announcing itself on the LAN
+    pub fn is_live(&self, peer: &PeerId) -> bool {
+        self.known.get(peer).is_some_and(|known| known.state() != "disconnected")
+    }
+
+    pub fn live(&self) -> impl Iterator<Item = &PeerId> {
+        self.known.iter().filter(|(_, known)| known.state() != "disconnected").map(|(peer, _)| peer)
+    }
+
+    // drop peers we haven't heard of in FORGET_AFTER
+    pub fn prune(&mut self) {
+        let now = SystemTime::now();
+        self.known.retain(|_, known| {
+            known.state() != "disconnected"
+                || now.duration_since(known.last_seen).unwrap_or_default() < FORGET_AFTER
+        });
+    }
+
+    fn info(&self, peer: &PeerId) -> Option<PeerInfo> {
+        let known = self.known.get(peer)?;
+        Some(PeerInfo {
+            peer_id: peer.to_string(),
+            state: known.state(),
+            connections: known.connections.len(),
+            addrs: known.addrs.iter().map(|addr| addr.to_string()).collect(),
+            latency_ms: known.latency.map(|rtt| rtt.as_millis() as u64),
+            agent: known.agent.clone(),
+            protocols: known.protocols.clone(),
+            last_seen: known.last_seen.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+        })
+    }
+
+    // connected peers first, then by peer ID
+    pub fn list(&self) -> Vec<PeerInfo> {
+        let mut peers: Vec<PeerInfo> = self.known.keys().filter_map(|peer| self.info(peer)).collect();
+        peers.sort_by(|a, b| (a.state != "connected", &a.peer_id).cmp(&(b.state != "connected", &b.peer_id)));
+        peers
+    }
+}
+
+// mDNS and identify addresses may end in /p2p/<peer>, listed once without it
+fn without_peer(addr: &Multiaddr) -> Multiaddr {
+    let mut addr = addr.clone();
+    if let Some(Protocol::P2p(_)) = addr.iter().last() {
+        addr.pop();
+    }
+    addr
+}
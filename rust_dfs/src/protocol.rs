@@ -0,0 +1,95 @@
+use async_trait::async_trait;
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2p::request_response::Codec;
+use serde::{Deserialize, Serialize};
+use std::io;
+
+use crate::chunk::Manifest;
+
+// largest request or response we accept, a chunk plus some room for the manifest of a big file
+pub const MAX_MESSAGE_SIZE: u64 = 8 * 1024 * 1024;
+// what identify reports, peers running rust_dfs can tell each other apart from other libp2p nodes
+pub const IDENTIFY_PROTOCOL: &str = "/rust_dfs/1.0.0";
+
+/// Protocol used to pull manifests and chunks from the peers providing a file.
+#[derive(Debug, Clone)]
+pub struct FileProtocol;
+impl AsRef<str> for FileProtocol {
+    fn as_ref(&self) -> &str {
+        "/rust_dfs/file/1.0.0"
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum FileRequest {
+    Manifest { key: String },
+    Chunk { hash: String },
+    Replicate { key: String }, // ask the peer to fetch and provide a file too
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum FileResponse {
+    Manifest(Manifest),
+    Chunk(Vec<u8>),
+    NotFound,
+    Accepted,
+}
+
+/// bincode over the stream, the message ends when the writer closes its side.
+#[derive(Debug, Clone, Default)]
+pub struct FileCodec;
+
+async fn read_message<T, M>(io: &mut T) -> io::Result<M>
+where
+    T: AsyncRead + Unpin + Send,
+    M: for<'de> Deserialize<'de>,
+{
+    let mut buf = Vec::new();
+    io.take(MAX_MESSAGE_SIZE).read_to_end(&mut buf).await?;
+    bincode::deserialize(&buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+async fn write_message<T, M>(io: &mut T, message: &M) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin + Send,
+    M: Serialize,
+{
+    let data = bincode::serialize(message).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    io.write_all(&data).await?;
+    io.close().await
+}
+
+#[async_trait]
+impl Codec for FileCodec {
+    type Protocol = FileProtocol;
+    type Request = FileRequest;
+    type Response = FileResponse;
+
+    async fn read_request<T>(&mut self, _protocol: &Self::Protocol, io: &mut T) -> io::Result<Self::Request>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_message(io).await
+    }
+
+    async fn read_response<T>(&mut self, _protocol: &Self::Protocol, io: &mut T) -> io::Result<Self::Response>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_message(io).await
+    }
+
+    async fn write_request<T>(&mut self, _protocol: &Self::Protocol, io: &mut T, req: Self::Request) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_message(io, &req).await
+    }
+
+    async fn write_response<T>(&mut self, _protocol: &Self::Protocol, io: &mut T, res: Self::Response) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_message(io, &res).await
+    }
+}
@@ -0,0 +1,64 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    time::Duration,
+};
+use libp2p::{kad::QueryId, PeerId};
+
+// copies of every file the network should hold, us included
+pub const DEFAULT_FACTOR: usize = 3;
+// how often the repair task counts the providers of every stored file
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+// provider counts of the files we hold and the checks still running
+pub struct Replication {
+    pub factor: usize,
+    checks: HashMap<QueryId, (String, HashSet<PeerId>)>,
+    live: BTreeMap<String, usize>, // live providers seen by the last finished check
+}
+
+impl Replication {
+    pub fn new(factor: usize) -> Self {
+        Replication {
+            factor: factor.max(1),
+            checks: HashMap::new(),
+            live: BTreeMap::new(),
+        }
+    }
+
+    pub fn is_checking(&self, key: &str) -> bool {
+        self.checks.values().any(|(k, _)| k == key)
+    }
+
+    pub fn is_check(&self, query: QueryId) -> bool {
+        self.checks.contains_key(&query)
+    }
+
+    // a get_providers query was started to count the providers of `key`
+    pub fn start(&mut self, key: &str, query: QueryId) {
+        self.checks.insert(query, (key.to_string(), HashSet::new()));
+    }
+
+    pub fn add_providers(&mut self, query: QueryId, providers: impl IntoIterator<Item = PeerId>) {
+        if let Some((_, found)) = self.checks.get_mut(&query) {
+            found.extend(providers);
+        }
+    }
+
+    // the query is over, returns the file key and every provider it found
+    pub fn finish(&mut self, query: QueryId) -> Option<(String, HashSet<PeerId>)> {
+        self.checks.remove(&query)
+    }
+
+    pub fn record(&mut self, key: &str, live: usize) {
+        self.live.insert(key.to_string(), live);
+    }
+
+    pub fn forget(&mut self, key: &str) {
+        self.live.remove(key);
+    }
+
+    // live provider count of a file, None until it was checked once
+    pub fn live(&self, key: &str) -> Option<usize> {
+        self.live.get(key).copied()
+    }
+}
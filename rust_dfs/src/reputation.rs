@@ -0,0 +1,180 @@
+use std::{
+    collections::HashMap,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+use libp2p::{kad::{ProviderRecord, Record}, PeerId};
+use serde::{Deserialize, Serialize};
+
+// connections to all peers, and to one peer: direct and relayed over a couple of transports
+pub const DEFAULT_MAX_CONNECTIONS: u32 = 256;
+pub const MAX_CONNECTIONS_PER_PEER: u32 = 4;
+pub const MAX_PENDING_INCOMING: u32 = 64;
+// manifest and chunk requests a peer may send per second, and as a burst
+pub const DEFAULT_REQUEST_RATE: u32 = 50;
+const REQUEST_BURST: u32 = 200;
+// DHT puts a peer may send per second, a node republishing all its files needs a large burst
+const PUT_RATE: u32 = 20;
+const PUT_BURST: u32 = 500;
+// largest record key and value stored for another peer, rust_dfs itself only puts provider records
+pub const MAX_KEY_BYTES: usize = 256;
+pub const MAX_RECORD_BYTES: usize = 8 * 1024;
+// records and provider records stored for one peer, a node announces the files it holds to
+// the K peers closest to each key and stays far below this
+pub const MAX_RECORDS_PER_PEER: usize = 4096;
+// a peer starts at 0, gains a point per verified chunk and is banned once it drops to BAN_SCORE
+const MAX_SCORE: i32 = 100;
+const BAN_SCORE: i32 = -100;
+
+/// Something a peer sent that failed validation.
+#[derive(Debug, Clone, Copy)]
+pub enum Offence {
+    WrongChunk,      // data that doesn't hash to the chunk we asked for
+    InvalidManifest, // chunk list, lengths and size don't agree, or the content doesn't hash to the key
+    OversizedRecord, // DHT record over the size limits
+}
+
+impl Offence {
+    fn penalty(self) -> i32 {
+        match self {
+            Offence::WrongChunk => 25,
+            Offence::InvalidManifest => 25,
+            Offence::OversizedRecord => 10,
+        }
+    }
+}
+
+// per-peer token buckets, `rate` tokens a second up to `burst`
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: HashMap<PeerId, (f64, Instant)>,
+}
+
+impl RateLimiter {
+    // 0 turns the limit off
+    pub fn new(rate: u32, burst: u32) -> Self {
+        RateLimiter {
+            rate: rate as f64,
+            burst: burst.max(rate) as f64,
+            buckets: HashMap::new(),
+        }
+    }
+
+    pub fn requests(rate: u32) -> Self {
+        Self::new(rate, REQUEST_BURST)
+    }
+
+    pub fn puts() -> Self {
+        Self::new(PUT_RATE, PUT_BURST)
+    }
+
+    // takes a token, false if the peer is over its rate
+    pub fn allow(&mut self, peer: PeerId) -> bool {
+        if self.rate == 0.0 {
+            return true;
+        }
+        let now = Instant::now();
+        // peers whose bucket has refilled are forgotten, the map stays as small as the busy peers
+        if self.buckets.len() > 1024 {
+            let (rate, burst) = (self.rate, self.burst);
+            self.buckets.retain(|_, (tokens, at)| *tokens + now.duration_since(*at).as_secs_f64() * rate < burst);
+        }
+        let (tokens, at) = self.buckets.entry(peer).or_insert((self.burst, now));
+        *tokens = (*tokens + now.duration_since(*at).as_secs_f64() * self.rate).min(self.burst);
+        *at = now;
+        if *tokens < 1.0 {
+            return false;
+        }
+        *tokens -= 1.0;
+        true
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Ban {
+    reason: String,
+    since: u64, // unix seconds
+}
+
+// scores of the peers we exchanged data with, and the ban list kept in sled
+pub struct Reputation {
+    scores: HashMap<PeerId, i32>,
+    bans: sled::Tree, // peer id -> Ban as JSON
+    banned: Vec<PeerId>, // banned since the last `take_banned`, to be disconnected
+}
+
+impl Reputation {
+    pub fn open(db: &sled::Db) -> sled::Result<Self> {
+        Ok(Reputation {
+            scores: HashMap::new(),
+            bans: db.open_tree("bans")?,
+            banned: Vec::new(),
+        })
+    }
+
+    pub fn score(&self, peer: &PeerId) -> i32 {
+        self.scores.get(peer).copied().unwrap_or(0)
+    }
+
+    pub fn reward(&mut self, peer: PeerId) {
+        let score = self.scores.entry(peer).or_default();
+        *score = (*score + 1).min(MAX_SCORE);
+    }
+
+    // lower the peer's score, a peer reaching BAN_SCORE is banned
+    pub fn penalize(&mut self, peer: PeerId, offence: Offence) {
+        let score = *self.scores.entry(peer).and_modify(|score| *score -= offence.penalty()).or_insert(-offence.penalty());
+        eprintln!("{peer} sent a {offence:?}, score {score}");
+        if score <= BAN_SCORE && !self.is_banned(&peer) {
+            let reason = format!("score fell to {score}, last offence {offence:?}");
+            if let Err(e) = self.ban(peer, &reason) {
+                eprintln!("Failed to ban {peer}: {e}");
+            }
+        }
+    }
+
+    pub fn is_banned(&self, peer: &PeerId) -> bool {
+        self.bans.contains_key(peer.to_bytes()).unwrap_or(false)
+    }
+
+    pub fn ban(&mut self, peer: PeerId, reason: &str) -> sled::Result<()> {
+        let since = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let ban = Ban { reason: reason.to_string(), since };
+        self.bans.insert(peer.to_bytes(), serde_json::to_vec(&ban).expect("ban serializes"))?;
+        println!("Banned {peer}: {reason}");
+        self.banned.push(peer);
+        Ok(())
+    }
+
+    // false if the peer wasn't banned. Its score starts over
+    pub fn unban(&mut self, peer: &PeerId) -> sled::Result<bool> {
+        self.scores.remove(peer);
+        Ok(self.bans.remove(peer.to_bytes())?.is_some())
+    }
+
+    // peers banned since the last call
+    pub fn take_banned(&mut self) -> Vec<PeerId> {
+        std::mem::take(&mut self.banned)
+    }
+
+    // every banned peer with the reason and when, for BANS and to block them at startup
+    pub fn bans(&self) -> Vec<(PeerId, String, u64)> {
+        self.bans
+            .iter()
+            .filter_map(|entry| {
+                let (peer, bytes) = entry.ok()?;
+                let ban: Ban = serde_json::from_slice(&bytes).ok()?;
+                Some((PeerId::from_bytes(&peer).ok()?, ban.reason, ban.since))
+            })
+            .collect()
+    }
+}
+
+// whether a record another peer puts may be stored
+pub fn valid_record(record: &Record) -> bool {
+    record.key.as_ref().len() <= MAX_KEY_BYTES && record.value.len() <= MAX_RECORD_BYTES
+}
+
+pub fn valid_provider(record: &ProviderRecord) -> bool {
+    record.key.as_ref().len() <= MAX_KEY_BYTES
+}
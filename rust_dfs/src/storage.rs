@@ -0,0 +1,360 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, Read},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use sha2::{Digest, Sha256};
+use sled::{
+    transaction::{ConflictableTransactionError, TransactionError, TransactionalTree, UnabortableTransactionError},
+    Transactional,
+};
+
+use crate::chunk::Manifest;
+use crate::protocol::{FileRequest, FileResponse};
+
+type TxResult<T> = Result<T, UnabortableTransactionError>;
+
+// manifests and chunks of every file this node provides, in their own sled trees.
+// A chunk is stored once, `refs` counts the manifests that use it
+#[derive(Clone)]
+pub struct Storage {
+    manifests: sled::Tree,
+    chunks: sled::Tree,
+    refs: sled::Tree,
+    bytes: Arc<AtomicU64>, // size of all chunks in the chunk tree, what the quota goes by
+}
+
+fn ref_count(refs: &TransactionalTree, hash: &str) -> TxResult<u64> {
+    let count = refs.get(hash)?.and_then(|bytes| Some(u64::from_be_bytes(bytes.as_ref().try_into().ok()?)));
+    Ok(count.unwrap_or(0))
+}
+
+// one manifest less uses `hash`, the chunk goes once nothing does. Returns the bytes freed
+fn release(refs: &TransactionalTree, chunks: &TransactionalTree, hash: &str) -> TxResult<u64> {
+    match ref_count(refs, hash)? {
+        0 | 1 => {
+            refs.remove(hash)?;
+            Ok(chunks.remove(hash)?.map_or(0, |data| data.len() as u64))
+        }
+        count => {
+            refs.insert(hash, &(count - 1).to_be_bytes())?;
+            Ok(0)
+        }
+    }
+}
+
+// a file repeating a chunk still holds one reference to it
+fn unique(manifest: &Manifest) -> HashSet<&String> {
+    manifest.chunks.iter().collect()
+}
+
+fn storage_error(e: TransactionError<()>) -> sled::Error {
+    match e {
+        TransactionError::Storage(e) => e,
+        TransactionError::Abort(()) => unreachable!("storage transactions never abort"),
+    }
+}
+
+impl Storage {
+    pub fn open(db: &sled::Db) -> sled::Result<Self> {
+        let storage = Storage {
+            manifests: db.open_tree("manifests")?,
+            chunks: db.open_tree("chunks")?,
+            refs: db.open_tree("chunk_refs")?,
+            bytes: Arc::default(),
+        };
+        // databases from before reference counting
+        if storage.refs.is_empty() && !storage.manifests.is_empty() {
+            storage.rebuild_refs()?;
+        }
+        storage.backfill_lengths()?;
+        let mut bytes = 0;
+        for data in storage.chunks.iter().values() {
+            bytes += data?.len() as u64;
+        }
+        storage.bytes.store(bytes, Ordering::Relaxed);
+        Ok(storage)
+    }
+
+    // bytes of every chunk held, shared ones once and unreferenced ones included
+    pub fn stored_bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    fn freed(&self, bytes: u64) {
+        self.bytes.fetch_sub(bytes, Ordering::Relaxed);
+    }
+
+    // manifests from before chunk lengths were recorded, read their chunks once to fill them in
+    fn backfill_lengths(&self) -> sled::Result<()> {
+        for entry in self.manifests.iter() {
+            let (key, bytes) = entry?;
+            let Some(mut manifest) = Manifest::from_bytes(&bytes) else {
+                continue;
+            };
+            if manifest.lengths.len() == manifest.chunks.len() {
+                continue;
+            }
+            let lengths: Option<Vec<u32>> = manifest
+                .chunks
+                .iter()
+                .map(|hash| Some(self.chunks.get(hash).ok()??.len() as u32))
+                .collect();
+            if let Some(lengths) = lengths {
+                manifest.lengths = lengths;
+                self.manifests.insert(key, manifest.to_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn rebuild_refs(&self) -> sled::Result<()> {
+        let mut counts: HashMap<String, u64> = HashMap::new();
+        for bytes in self.manifests.iter().values() {
+            if let Some(manifest) = Manifest::from_bytes(&bytes?) {
+                for hash in unique(&manifest) {
+                    *counts.entry(hash.clone()).or_default() += 1;
+                }
+            }
+        }
+        for (hash, count) in counts {
+            self.refs.insert(hash, &count.to_be_bytes())?;
+        }
+        Ok(())
+    }
+
+    // keep a chunk unless it's stored already, returns true if it was new. The chunk is
+    // unreferenced until `commit` names it in a manifest
+    pub fn put_chunk(&self, hash: &str, data: &[u8]) -> sled::Result<bool> {
+        let inserted = self.chunks.compare_and_swap(hash, None as Option<&[u8]>, Some(data))?.is_ok();
+        if inserted {
+            self.bytes.fetch_add(data.len() as u64, Ordering::Relaxed);
+        }
+        Ok(inserted)
+    }
+
+    // store a file's manifest once all its chunks are in, chunks held for other files are
+    // only referenced again. A chunk `put_chunk` found already stored may have been collected
+    // since, the put fails then instead of naming a chunk that's gone
+    pub fn commit(&self, key: &str, manifest: &Manifest) -> Result<(), String> {
+        let freed = (&self.manifests, &self.chunks, &self.refs)
+            .transaction(|(manifests, stored, refs)| {
+                for hash in unique(manifest) {
+                    if stored.get(hash)?.is_none() {
+                        return Err(ConflictableTransactionError::Abort(format!("chunk {hash} is missing")));
+                    }
+                    let count = ref_count(refs, hash)?;
+                    refs.insert(hash.as_str(), &(count + 1).to_be_bytes())?;
+                }
+                // a file stored again under the same key drops its old references
+                let mut freed = 0;
+                if let Some(old) = manifests.insert(key, manifest.to_bytes())?
+                    && let Some(old) = Manifest::from_bytes(&old)
+                {
+                    for hash in unique(&old) {
+                        freed += release(refs, stored, hash)?;
+                    }
+                }
+                Ok(freed)
+            })
+            .map_err(|e| match e {
+                TransactionError::Storage(e) => e.to_string(),
+                TransactionError::Abort(reason) => reason,
+            })?;
+        self.freed(freed);
+        Ok(())
+    }
+
+    // the content of a stored file, or the bytes from `start` to `end` inclusive, read a chunk at a time
+    pub fn reader(&self, manifest: &Manifest, range: Option<(u64, u64)>) -> ChunkReader {
+        let (start, end) = range.unwrap_or((0, manifest.size.saturating_sub(1)));
+        let mut reader = ChunkReader {
+            chunks: self.chunks.clone(),
+            hashes: manifest.chunks.clone(),
+            next: 0,
+            skip: start,
+            remaining: if manifest.size == 0 { 0 } else { end + 1 - start },
+            buffer: Vec::new(),
+            pos: 0,
+        };
+        // whole chunks before the range are skipped without reading them
+        if manifest.lengths.len() == manifest.chunks.len() {
+            for length in &manifest.lengths {
+                if reader.skip < *length as u64 {
+                    break;
+                }
+                reader.skip -= *length as u64;
+                reader.next += 1;
+            }
+        }
+        reader
+    }
+
+    // read every chunk of a downloaded file once, returns the sha256 of the content and the
+    // chunk lengths. Fails if a chunk is missing or the sizes don't add up
+    pub fn digest(&self, manifest: &Manifest) -> Result<(String, Vec<u32>), String> {
+        let mut hasher = Sha256::new();
+        let mut lengths = Vec::with_capacity(manifest.chunks.len());
+        for hash in &manifest.chunks {
+            let data = self.chunks.get(hash).map_err(|e| e.to_string())?.ok_or(format!("chunk {hash} is missing"))?;
+            hasher.update(&data);
+            lengths.push(data.len() as u32);
+        }
+        let size: u64 = lengths.iter().map(|length| *length as u64).sum();
+        if size != manifest.size {
+            return Err(format!("expected {} bytes, chunks hold {size}", manifest.size));
+        }
+        Ok((format!("{:x}", hasher.finalize()), lengths))
+    }
+
+    pub fn manifest(&self, key: &str) -> Option<Manifest> {
+        let bytes = self.manifests.get(key).ok()??;
+        Manifest::from_bytes(&bytes)
+    }
+
+    pub fn chunk(&self, hash: &str) -> Option<Vec<u8>> {
+        self.chunks.get(hash).ok()?.map(|bytes| bytes.to_vec())
+    }
+
+    pub fn has_chunk(&self, hash: &str) -> bool {
+        self.chunks.contains_key(hash).unwrap_or(false)
+    }
+
+    pub fn chunk_len(&self, hash: &str) -> Option<u64> {
+        Some(self.chunks.get(hash).ok()??.len() as u64)
+    }
+
+    // drop a file's manifest and the chunks no other file uses, false if there was no manifest
+    pub fn remove_file(&self, key: &str) -> sled::Result<bool> {
+        let removed = (&self.manifests, &self.chunks, &self.refs)
+            .transaction(|(manifests, stored, refs)| {
+                let Some(bytes) = manifests.remove(key)? else {
+                    return Ok(None);
+                };
+                let mut freed = 0;
+                if let Some(manifest) = Manifest::from_bytes(&bytes) {
+                    for hash in unique(&manifest) {
+                        freed += release(refs, stored, hash)?;
+                    }
+                }
+                Ok::<_, ConflictableTransactionError<()>>(Some(freed))
+            })
+            .map_err(storage_error)?;
+        if let Some(freed) = removed {
+            self.freed(freed);
+        }
+        Ok(removed.is_some())
+    }
+
+    // drop chunks no manifest references, except the `pending` ones of downloads still running.
+    // Returns how many went and their size in bytes
+    pub fn collect_garbage(&self, pending: &HashSet<String>) -> sled::Result<(usize, u64)> {
+        let (mut count, mut bytes) = (0, 0);
+        for entry in self.chunks.iter() {
+            let (hash, data) = entry?;
+            if self.refs.contains_key(&hash)? || pending.contains(String::from_utf8_lossy(&hash).as_ref()) {
+                continue;
+            }
+            // a commit may have referenced the chunk since, check again along with the removal
+            let removed = (&self.chunks, &self.refs)
+                .transaction(|(stored, refs)| {
+                    if refs.get(&hash)?.is_some() {
+                        return Ok(false);
+                    }
+                    stored.remove(&hash)?;
+                    Ok::<_, ConflictableTransactionError<()>>(true)
+                })
+                .map_err(storage_error)?;
+            if removed {
+                self.freed(data.len() as u64);
+                count += 1;
+                bytes += data.len() as u64;
+            }
+        }
+        Ok((count, bytes))
+    }
+
+    // keys of all files we can serve, announced as provider records at startup
+    pub fn file_keys(&self) -> Vec<String> {
+        self.manifests
+            .iter()
+            .keys()
+            .filter_map(|key| key.ok())
+            .map(|key| String::from_utf8_lossy(&key).to_string())
+            .collect()
+    }
+
+    // answer a request from another peer
+    pub fn respond(&self, request: FileRequest) -> FileResponse {
+        match request {
+            FileRequest::Manifest { key } => self.manifest(&key).map(FileResponse::Manifest),
+            FileRequest::Chunk { hash } => self.chunk(&hash).map(FileResponse::Chunk),
+            FileRequest::Replicate { .. } => None, // needs the swarm, handled by the node
+        }
+        .unwrap_or(FileResponse::NotFound)
+    }
+}
+
+/// Reads a stored file in order without loading more than one chunk, as an iterator of chunks
+/// or through `Read`.
+pub struct ChunkReader {
+    chunks: sled::Tree,
+    hashes: Vec<String>,
+    next: usize,    // index of the next chunk to read
+    skip: u64,      // bytes to drop from the front of the next chunk
+    remaining: u64, // bytes left in the range
+    buffer: Vec<u8>,
+    pos: usize,
+}
+
+impl Iterator for ChunkReader {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos < self.buffer.len() {
+            let rest = self.buffer.split_off(self.pos);
+            self.pos = 0;
+            self.buffer.clear();
+            return Some(Ok(rest));
+        }
+        while self.remaining > 0 {
+            let hash = self.hashes.get(self.next)?;
+            self.next += 1;
+            let data = match self.chunks.get(hash) {
+                Ok(Some(data)) => data,
+                Ok(None) => return Some(Err(io::Error::new(io::ErrorKind::NotFound, format!("chunk {hash} is missing")))),
+                Err(e) => return Some(Err(io::Error::other(e))),
+            };
+            let start = self.skip.min(data.len() as u64) as usize;
+            self.skip -= start as u64;
+            let end = (start as u64 + self.remaining).min(data.len() as u64) as usize;
+            if start == end {
+                continue;
+            }
+            self.remaining -= (end - start) as u64;
+            return Some(Ok(data[start..end].to_vec()));
+        }
+        None
+    }
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.buffer.len() {
+            match self.next() {
+                Some(chunk) => {
+                    self.buffer = chunk?;
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let n = out.len().min(self.buffer.len() - self.pos);
+        out[..n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
@@ -0,0 +1,128 @@
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::chunk;
+use crate::directory::{self, Kind};
+use crate::storage::Storage;
+
+// every entry in the default sled tree starts with this, older entries don't
+const MAGIC: &[u8; 4] = b"DFSF";
+pub const FORMAT_VERSION: u8 = 2;
+
+/// A file as listed in the default sled tree, written the same way by PUT_FILE and by downloads.
+/// The content is in the chunk store, under the manifest of the same key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredFile {
+    pub name: String,
+    pub size: u64,
+    pub mime: String,
+    pub hash: String, // sha256 of the content, hex
+}
+
+// format 1 kept the content in the record
+#[derive(Deserialize)]
+struct RecordV1 {
+    name: String,
+    content: Vec<u8>,
+}
+
+impl StoredFile {
+    // `kind` is what the manifest says, directory nodes get their own MIME type
+    pub fn new(name: &str, size: u64, hash: String, kind: Kind) -> Self {
+        StoredFile {
+            name: name.to_string(),
+            size,
+            mime: match kind {
+                Kind::Directory => directory::MIME.to_string(),
+                Kind::File => mime_guess::from_path(name).first_or_octet_stream().to_string(),
+            },
+            hash,
+        }
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.mime == directory::MIME
+    }
+
+    // magic, format version, then the bincode encoded record
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(FORMAT_VERSION);
+        bytes.extend(bincode::serialize(self).expect("stored file serializes"));
+        bytes
+    }
+
+    // None for entries in an older layout, `migrate` rewrites those at startup
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let body = bytes.strip_prefix(MAGIC)?;
+        match body.split_first()? {
+            (2, record) => bincode::deserialize(record).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn load(db: &sled::Db, key: &str) -> Option<Self> {
+        Self::from_bytes(&db.get(key).ok()??)
+    }
+
+    pub fn save(&self, db: &sled::Db, key: &str) -> sled::Result<()> {
+        db.insert(key, self.to_bytes()).map(|_| ())
+    }
+}
+
+// name and content of an entry written before format 2: a format 1 record, `filename|base64`
+// from PUT_FILE or raw bytes from the network
+fn old_content(key: &str, bytes: &[u8]) -> (String, Vec<u8>) {
+    if let Some((1, record)) = bytes.strip_prefix(MAGIC).and_then(|body| body.split_first())
+        && let Ok(record) = bincode::deserialize::<RecordV1>(record)
+    {
+        return (record.name, record.content);
+    }
+    let decoded = std::str::from_utf8(bytes).ok().and_then(|text| {
+        let (name, encoded) = text.split_once('|')?;
+        let content = base64::engine::general_purpose::STANDARD.decode(encoded).ok()?;
+        Some((name.to_string(), content))
+    });
+    decoded.unwrap_or_else(|| (key.to_string(), bytes.to_vec()))
+}
+
+// move the content of older entries of the default tree into the chunk store and keep only
+// the StoredFile record, returns how many were converted. Directory nodes stored before
+// manifests had a kind get it from their record
+pub fn migrate(db: &sled::Db, storage: &Storage) -> Result<usize, String> {
+    let mut migrated = 0;
+    for entry in db.iter() {
+        let (key, value) = entry.map_err(|e| e.to_string())?;
+        let key = String::from_utf8_lossy(&key).to_string();
+        if let Some(file) = StoredFile::from_bytes(&value) {
+            if file.is_directory()
+                && let Some(mut manifest) = storage.manifest(&key)
+                && manifest.kind != Kind::Directory
+            {
+                manifest.kind = Kind::Directory;
+                storage.commit(&key, &manifest).map_err(|e| format!("{key}: {e}"))?;
+                migrated += 1;
+            }
+            continue;
+        }
+        let (name, content) = old_content(&key, &value);
+        // files chunked before keep the manifest they are provided under
+        let hash = if storage.manifest(&key).is_some() {
+            format!("{:x}", Sha256::digest(&content))
+        } else {
+            let (manifest, hash) = chunk::split(&name, content.as_slice(), |hash, data| {
+                storage.put_chunk(hash, data).map(|_| ()).map_err(std::io::Error::other)
+            })
+            .map_err(|e| format!("{key}: {e}"))?;
+            storage.commit(&key, &manifest).map_err(|e| format!("{key}: {e}"))?;
+            hash
+        };
+        // entries from before format 2 predate directories
+        StoredFile::new(&name, content.len() as u64, hash, Kind::File)
+            .save(db, &key)
+            .map_err(|e| format!("{key}: {e}"))?;
+        migrated += 1;
+    }
+    Ok(migrated)
+}
@@ -0,0 +1,487 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    time::{Duration, Instant},
+};
+use libp2p::{
+    kad::QueryId,
+    request_response::{self, OutboundRequestId},
+    PeerId,
+};
+use serde::Serialize;
+
+use crate::chunk::{Download, Manifest};
+use crate::protocol::{FileCodec, FileRequest, FileResponse};
+use crate::reputation::{Offence, Reputation};
+use crate::storage::Storage;
+
+pub type TransferBehaviour = request_response::Behaviour<FileCodec>;
+
+// chunk requests kept in flight for one download
+pub const MAX_IN_FLIGHT: usize = 8;
+// a provider that failed this many times is not asked again
+const MAX_PROVIDER_FAILURES: usize = 2;
+// how often paused downloads are tried again
+pub const RESUME_INTERVAL: Duration = Duration::from_secs(30);
+
+pub enum Outcome {
+    Complete(Download, Option<PeerId>), // with the peer that sent the manifest, none for a session from before a restart
+    Failed(String),
+}
+
+// state of one file being pulled from its providers
+struct Fetch {
+    providers: Vec<PeerId>,
+    failures: HashMap<PeerId, usize>,
+    next: usize,       // round robin position in `providers`
+    searching: bool,   // provider query still running
+    manifest_pending: bool,
+    manifest_from: Option<PeerId>,
+    download: Option<Download>,
+    queue: VecDeque<String>, // chunk hashes not requested yet
+    in_flight: usize,
+    started: Instant,
+    fetched: u64,          // bytes received since this run started, for the rate
+    senders: HashSet<PeerId>, // providers that sent chunks in this run
+    reported: u64,         // tenths of the file last printed
+}
+
+impl Fetch {
+    fn new() -> Self {
+        Fetch {
+            providers: Vec::new(),
+            failures: HashMap::new(),
+            next: 0,
+            searching: true,
+            manifest_pending: false,
+            manifest_from: None,
+            download: None,
+            queue: VecDeque::new(),
+            in_flight: 0,
+            started: Instant::now(),
+            fetched: 0,
+            senders: HashSet::new(),
+            reported: 0,
+        }
+    }
+
+    // the manifest is known, queue what isn't stored yet
+    fn begin(&mut self, mut download: Download, storage: &Storage) {
+        download.mark_stored(stored_length(storage, &download.manifest));
+        self.queue = download.missing().into();
+        self.reported = tenths(&download);
+        self.download = Some(download);
+    }
+
+    // next provider that hasn't failed too often
+    fn pick_provider(&mut self) -> Option<PeerId> {
+        for _ in 0..self.providers.len() {
+            let peer = self.providers[self.next % self.providers.len()];
+            self.next += 1;
+            if self.failures.get(&peer).copied().unwrap_or(0) < MAX_PROVIDER_FAILURES {
+                return Some(peer);
+            }
+        }
+        None
+    }
+
+    fn record_failure(&mut self, peer: PeerId) {
+        *self.failures.entry(peer).or_default() += 1;
+    }
+
+    // a line every tenth of the file, the last one is followed by the download finishing
+    fn report(&mut self, key: &str) {
+        let Some(download) = &self.download else {
+            return;
+        };
+        let done = tenths(download);
+        if done > self.reported && done < 10 {
+            self.reported = done;
+            let rate = self.fetched as f64 / self.started.elapsed().as_secs_f64().max(0.001);
+            println!(
+                "Downloading {key}: {}% ({}/{} chunks, {} peers, {} KiB/s)",
+                done * 10,
+                download.received(),
+                download.manifest.chunks.len(),
+                self.senders.len(),
+                rate as u64 / 1024
+            );
+        }
+    }
+}
+
+// length of a chunk we already hold, from the manifest when it lists lengths
+fn stored_length(storage: &Storage, manifest: &Manifest) -> impl Fn(&str) -> Option<u64> + use<> {
+    let lengths: HashMap<String, u32> = manifest.chunks.iter().cloned().zip(manifest.lengths.iter().copied()).collect();
+    let storage = storage.clone();
+    move |hash| match lengths.get(hash) {
+        Some(length) => storage.has_chunk(hash).then_some(*length as u64),
+        None => storage.chunk_len(hash),
+    }
+}
+
+fn tenths(download: &Download) -> u64 {
+    (download.bytes() * 10).checked_div(download.manifest.size).unwrap_or(10)
+}
+
+/// Where a download stands, for the DOWNLOADS command and `GET /downloads`.
+#[derive(Serialize)]
+pub struct Progress {
+    pub key: String,
+    pub state: &'static str, // searching, fetching or paused
+    pub name: Option<String>, // name, size and chunk count are unknown until a provider sends the manifest
+    pub size: Option<u64>,
+    pub bytes: u64,
+    pub chunks: Option<usize>,
+    pub received: usize,
+    pub providers: usize,
+    pub peers: usize, // providers that sent chunks in this run
+    pub rate: u64,    // bytes per second in this run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>, // why a paused download stopped
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} | {}", self.key, self.state)?;
+        if let (Some(name), Some(size), Some(chunks)) = (&self.name, self.size, self.chunks) {
+            let percent = (self.bytes * 100).checked_div(size).unwrap_or(100);
+            write!(
+                f,
+                " | {name} | {}/{size} bytes ({percent}%) | {}/{chunks} chunks",
+                self.bytes, self.received
+            )?;
+        }
+        write!(f, " | {}/{} peers | {} KiB/s", self.peers, self.providers, self.rate / 1024)?;
+        if let Some(reason) = &self.reason {
+            write!(f, " | {reason}")?;
+        }
+        Ok(())
+    }
+}
+
+enum Pending {
+    Manifest { key: String },
+    Chunk { key: String, hash: String },
+}
+
+impl Pending {
+    fn key(&self) -> &str {
+        match self {
+            Pending::Manifest { key } | Pending::Chunk { key, .. } => key,
+        }
+    }
+}
+
+// all running downloads and the queries/requests that belong to them. A download with a
+// manifest is a session in sled until it completes, its chunks are stored as they arrive,
+// so it picks up where it stopped after a restart or once a provider is back
+pub struct Transfers {
+    fetches: HashMap<String, Fetch>,
+    queries: HashMap<QueryId, String>,
+    requests: HashMap<OutboundRequestId, (PeerId, Pending)>,
+    sessions: sled::Tree,            // file key -> manifest of an unfinished download
+    paused: HashMap<String, String>, // sessions that stopped, with the reason
+    distrusted: HashMap<String, HashSet<PeerId>>, // providers whose manifest didn't match the key, skipped by its next fetch
+}
+
+impl Transfers {
+    pub fn open(db: &sled::Db) -> sled::Result<Self> {
+        Ok(Transfers {
+            fetches: HashMap::new(),
+            queries: HashMap::new(),
+            requests: HashMap::new(),
+            sessions: db.open_tree("downloads")?,
+            paused: HashMap::new(),
+            distrusted: HashMap::new(),
+        })
+    }
+
+    pub fn is_active(&self, key: &str) -> bool {
+        self.fetches.contains_key(key)
+    }
+
+    fn session(&self, key: &str) -> Option<Manifest> {
+        Manifest::from_bytes(&self.sessions.get(key).ok()??)
+    }
+
+    fn sessions(&self) -> impl Iterator<Item = (String, Manifest)> + '_ {
+        self.sessions.iter().filter_map(|entry| {
+            let (key, bytes) = entry.ok()?;
+            Some((String::from_utf8_lossy(&key).to_string(), Manifest::from_bytes(&bytes)?))
+        })
+    }
+
+    pub fn has_session(&self, key: &str) -> bool {
+        self.sessions.contains_key(key).unwrap_or(false)
+    }
+
+    // unfinished downloads that aren't running, after a restart or once their providers failed
+    pub fn resumable(&self) -> Vec<String> {
+        self.sessions().map(|(key, _)| key).filter(|key| !self.is_active(key)).collect()
+    }
+
+    // a download with a session failed, returns false if it had stopped before
+    pub fn pause(&mut self, key: &str, reason: &str) -> bool {
+        self.paused.insert(key.to_string(), reason.to_string()).is_none()
+    }
+
+    // the download completed or is given up, its chunks are left to the manifest or to GC
+    pub fn end(&mut self, key: &str) {
+        self.paused.remove(key);
+        if let Err(e) = self.sessions.remove(key) {
+            eprintln!("Failed to drop download session {key}: {e}");
+        }
+    }
+
+    // stop a download, running or paused. Its requests are forgotten, answers still in flight
+    // are ignored and can't be taken for those of a later fetch. Returns false if there was
+    // nothing to cancel
+    pub fn cancel(&mut self, key: &str) -> bool {
+        let running = self.fetches.remove(key).is_some();
+        self.queries.retain(|_, k| k != key);
+        self.requests.retain(|_, (_, pending)| pending.key() != key);
+        self.distrusted.remove(key);
+        let session = self.has_session(key);
+        self.end(key);
+        running || session
+    }
+
+    // chunks of unfinished downloads, stored but not referenced by a manifest yet
+    pub fn pending_chunks(&self) -> HashSet<String> {
+        self.sessions().flat_map(|(_, manifest)| manifest.chunks).collect()
+    }
+
+    pub fn progress(&self, storage: &Storage) -> Vec<Progress> {
+        let mut progress: Vec<Progress> = self
+            .fetches
+            .iter()
+            .map(|(key, fetch)| {
+                let elapsed = fetch.started.elapsed().as_secs_f64().max(0.001);
+                let download = fetch.download.as_ref();
+                Progress {
+                    key: key.clone(),
+                    state: if download.is_some() { "fetching" } else { "searching" },
+                    name: download.map(|d| d.manifest.filename.clone()),
+                    size: download.map(|d| d.manifest.size),
+                    bytes: download.map_or(0, Download::bytes),
+                    chunks: download.map(|d| d.manifest.chunks.len()),
+                    received: download.map_or(0, Download::received),
+                    providers: fetch.providers.len(),
+                    peers: fetch.senders.len(),
+                    rate: (fetch.fetched as f64 / elapsed) as u64,
+                    reason: None,
+                }
+            })
+            .collect();
+        for (key, manifest) in self.sessions().filter(|(key, _)| !self.is_active(key)) {
+            let mut download = Download::new(manifest);
+            download.mark_stored(stored_length(storage, &download.manifest));
+            progress.push(Progress {
+                reason: Some(self.paused.get(&key).cloned().unwrap_or_else(|| "waiting to resume".to_string())),
+                key,
+                state: "paused",
+                name: Some(download.manifest.filename.clone()),
+                size: Some(download.manifest.size),
+                bytes: download.bytes(),
+                chunks: Some(download.manifest.chunks.len()),
+                received: download.received(),
+                providers: 0,
+                peers: 0,
+                rate: 0,
+            });
+        }
+        progress.sort_by(|a, b| a.key.cmp(&b.key));
+        progress
+    }
+
+    // the manifest `peer` sent for `key` described other content, don't take it from them again
+    pub fn distrust(&mut self, key: &str, peer: PeerId) {
+        self.distrusted.entry(key.to_string()).or_default().insert(peer);
+    }
+
+    // a get_providers query was started for `key`, a session left from before carries on
+    pub fn start(&mut self, storage: &Storage, key: &str, query: QueryId) {
+        self.queries.insert(query, key.to_string());
+        let mut fetch = Fetch::new();
+        for peer in self.distrusted.remove(key).unwrap_or_default() {
+            fetch.failures.insert(peer, MAX_PROVIDER_FAILURES);
+        }
+        if let Some(manifest) = self.session(key) {
+            fetch.begin(Download::new(manifest), storage);
+            let download = fetch.download.as_ref().expect("just begun");
+            println!(
+                "Resuming download of {key}: {}/{} chunks already here",
+                download.received(),
+                download.manifest.chunks.len()
+            );
+        }
+        self.fetches.insert(key.to_string(), fetch);
+    }
+
+    pub fn add_providers(
+        &mut self,
+        rr: &mut TransferBehaviour,
+        query: QueryId,
+        providers: impl IntoIterator<Item = PeerId>,
+    ) -> Option<(String, Outcome)> {
+        let key = self.queries.get(&query)?.clone();
+        let fetch = self.fetches.get_mut(&key)?;
+        for peer in providers {
+            if !fetch.providers.contains(&peer) {
+                fetch.providers.push(peer);
+            }
+        }
+        self.pump(rr, &key)
+    }
+
+    // the provider query is over, fail if nobody has the file
+    pub fn providers_done(&mut self, rr: &mut TransferBehaviour, query: QueryId) -> Option<(String, Outcome)> {
+        let key = self.queries.remove(&query)?;
+        let fetch = self.fetches.get_mut(&key)?;
+        fetch.searching = false;
+        let complete = fetch.download.as_ref().is_some_and(Download::is_complete);
+        if fetch.providers.is_empty() && !complete {
+            self.fetches.remove(&key);
+            return Some((key, Outcome::Failed("no providers found".to_string())));
+        }
+        self.pump(rr, &key)
+    }
+
+    // verified chunks are written to `storage` right away, a download never holds the whole file.
+    // Peers sending invalid manifests or chunks lose reputation
+    pub fn on_response(
+        &mut self,
+        rr: &mut TransferBehaviour,
+        storage: &Storage,
+        reputation: &mut Reputation,
+        request_id: OutboundRequestId,
+        response: FileResponse,
+    ) -> Option<(String, Outcome)> {
+        let (peer, pending) = self.requests.remove(&request_id)?;
+        let key = match pending {
+            Pending::Manifest { key } => {
+                let fetch = self.fetches.get_mut(&key)?;
+                fetch.manifest_pending = false;
+                match response {
+                    FileResponse::Manifest(manifest) if !manifest.is_valid() => {
+                        reputation.penalize(peer, Offence::InvalidManifest);
+                        fetch.record_failure(peer);
+                    }
+                    FileResponse::Manifest(manifest) if fetch.download.is_none() => {
+                        println!(
+                            "Found manifest for {}: {} ({} bytes, {} chunks) from {}",
+                            key, manifest.filename, manifest.size, manifest.chunks.len(), peer
+                        );
+                        if let Err(e) = self.sessions.insert(key.as_str(), manifest.to_bytes()) {
+                            eprintln!("Failed to save download session {key}: {e}");
+                        }
+                        fetch.manifest_from = Some(peer);
+                        fetch.begin(Download::new(manifest), storage);
+                    }
+                    _ => fetch.record_failure(peer),
+                }
+                key
+            }
+            Pending::Chunk { key, hash } => {
+                let fetch = self.fetches.get_mut(&key)?;
+                fetch.in_flight -= 1;
+                let accepted = match (response, fetch.download.as_mut()) {
+                    (FileResponse::Chunk(data), Some(download)) if download.manifest.chunk_id(&data) != hash => {
+                        reputation.penalize(peer, Offence::WrongChunk);
+                        false
+                    }
+                    (FileResponse::Chunk(data), Some(download)) => {
+                        let accepted = download.insert(&hash, &data) && storage.put_chunk(&hash, &data).is_ok();
+                        if accepted {
+                            fetch.fetched += data.len() as u64;
+                            fetch.senders.insert(peer);
+                            reputation.reward(peer);
+                        }
+                        accepted
+                    }
+                    _ => false,
+                };
+                // missing or corrupted chunk, ask someone else
+                if !accepted {
+                    eprintln!("Bad or missing chunk {hash} from {peer}, retrying");
+                    fetch.record_failure(peer);
+                    fetch.queue.push_front(hash);
+                }
+                fetch.report(&key);
+                key
+            }
+        };
+        self.pump(rr, &key)
+    }
+
+    pub fn on_failure(&mut self, rr: &mut TransferBehaviour, request_id: OutboundRequestId) -> Option<(String, Outcome)> {
+        let (peer, pending) = self.requests.remove(&request_id)?;
+        let key = match pending {
+            Pending::Manifest { key } => {
+                let fetch = self.fetches.get_mut(&key)?;
+                fetch.manifest_pending = false;
+                fetch.record_failure(peer);
+                key
+            }
+            Pending::Chunk { key, hash } => {
+                let fetch = self.fetches.get_mut(&key)?;
+                fetch.in_flight -= 1;
+                fetch.record_failure(peer);
+                fetch.queue.push_front(hash);
+                key
+            }
+        };
+        self.pump(rr, &key)
+    }
+
+    // send as many requests as allowed and report when the download is over
+    fn pump(&mut self, rr: &mut TransferBehaviour, key: &str) -> Option<(String, Outcome)> {
+        let fetch = self.fetches.get_mut(key)?;
+
+        if fetch.download.is_none() {
+            if !fetch.manifest_pending {
+                match fetch.pick_provider() {
+                    Some(peer) => {
+                        let id = rr.send_request(&peer, FileRequest::Manifest { key: key.to_string() });
+                        self.requests.insert(id, (peer, Pending::Manifest { key: key.to_string() }));
+                        fetch.manifest_pending = true;
+                    }
+                    None if !fetch.searching => {
+                        self.fetches.remove(key);
+                        return Some((key.to_string(), Outcome::Failed("no provider sent the manifest".to_string())));
+                    }
+                    None => {} // wait for more providers
+                }
+            }
+            return None;
+        }
+
+        while fetch.in_flight < MAX_IN_FLIGHT && !fetch.queue.is_empty() {
+            let Some(peer) = fetch.pick_provider() else {
+                break;
+            };
+            let hash = fetch.queue.pop_front()?;
+            let id = rr.send_request(&peer, FileRequest::Chunk { hash: hash.clone() });
+            self.requests.insert(id, (peer, Pending::Chunk { key: key.to_string(), hash }));
+            fetch.in_flight += 1;
+        }
+
+        let complete = fetch.download.as_ref().is_some_and(Download::is_complete);
+        let stuck = fetch.in_flight == 0 && !fetch.queue.is_empty() && !fetch.searching;
+        if complete || stuck {
+            let fetch = self.fetches.remove(key)?;
+            let download = fetch.download?;
+            if complete {
+                return Some((key.to_string(), Outcome::Complete(download, fetch.manifest_from)));
+            }
+            let reason = format!(
+                "all providers failed ({}/{} chunks received)",
+                download.received(),
+                download.manifest.chunks.len()
+            );
+            return Some((key.to_string(), Outcome::Failed(reason)));
+        }
+        None
+    }
+}
@@ -0,0 +1,359 @@
+// nodes running in the test process, connected only to the peers a test gives them
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use futures::StreamExt;
+use libp2p::{
+    identify,
+    kad::{self, store::MemoryStore, Mode},
+    request_response::{self, ProtocolSupport},
+    swarm::{NetworkBehaviour, SwarmEvent},
+    Multiaddr, PeerId,
+};
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+use rust_dfs::{
+    chunk,
+    control::{self, PutAccess},
+    gateway::Command,
+    node::{self, Options},
+    protocol::{FileProtocol, FileRequest, FileResponse, IDENTIFY_PROTOCOL},
+    transfer::TransferBehaviour,
+    transport::{self, Transports},
+};
+use serde_json::{json, Value};
+use tokio::{
+    sync::mpsc,
+    task::{AbortHandle, JoinHandle},
+    time,
+};
+
+// every wait in a test gives up after this long
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+// repair and resume run this often instead of every minute and every 30 seconds
+pub const TICK: Duration = Duration::from_millis(500);
+
+// tests run in parallel in one process, each gets its own directory
+static NETWORKS: AtomicUsize = AtomicUsize::new(0);
+
+/// The nodes of one test, with their databases in a directory of their own. `stop` removes it,
+/// a test that fails before then leaves it behind to look into.
+pub struct Network {
+    dir: PathBuf,
+    transport: &'static str,
+    listen: &'static str,
+    tasks: Vec<JoinHandle<()>>, // one per node started, killed ones included
+}
+
+impl Network {
+    // nodes connected over the in-process memory transport
+    pub fn memory() -> Self {
+        Self::new("memory", "/memory/0")
+    }
+
+    // nodes connected over TCP on the loopback interface
+    pub fn tcp() -> Self {
+        Self::new("tcp", "/ip4/127.0.0.1/tcp/0")
+    }
+
+    fn new(transport: &'static str, listen: &'static str) -> Self {
+        let id = NETWORKS.fetch_add(1, Ordering::SeqCst);
+        let dir = std::env::temp_dir().join(format!("rust_dfs-test-{}-{id}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("create the test directory");
+        Network { dir, transport, listen, tasks: Vec::new() }
+    }
+
+    /// Starts a node storing `factor` copies of every file, bootstrapped to `peers` and
+    /// connected to all of them once this returns. mDNS is off, nodes only find each other
+    /// through the test.
+    pub async fn start(&mut self, factor: usize, peers: &[&Node]) -> Node {
+        self.start_with(factor, peers, &[]).await
+    }
+
+    /// Like `start`, with `extra` command line arguments.
+    pub async fn start_with(&mut self, factor: usize, peers: &[&Node], extra: &[&str]) -> Node {
+        let dir = self.dir.join(format!("node{}", self.tasks.len()));
+        let mut args: Vec<String> = vec![
+            dir.join("db").display().to_string(),
+            "--http".into(),
+            "127.0.0.1:0".into(),
+            "--no-mdns".into(),
+            "--relay".into(),
+            "off".into(),
+            "--replication".into(),
+            factor.to_string(),
+            "--retrieved".into(),
+            dir.join("retrieved").display().to_string(),
+            "--transport".into(),
+            self.transport.into(),
+            "--listen".into(),
+            self.listen.into(),
+        ];
+        for peer in peers {
+            args.extend(["--bootstrap".to_string(), peer.addr.to_string()]);
+        }
+        args.extend(extra.iter().map(|arg| arg.to_string()));
+        let mut options = Options::parse(args).expect("valid options");
+        options.stdin = false;
+        options.repair_interval = TICK;
+        options.resume_interval = TICK;
+        let (handle, run) = node::start(options).expect("node starts");
+        let task = tokio::spawn(run);
+        let mut node = Node {
+            peer_id: handle.peer_id,
+            addr: Multiaddr::empty(),
+            retrieved: dir.join("retrieved"),
+            commands: handle.commands,
+            task: task.abort_handle(),
+        };
+        self.tasks.push(task);
+
+        let status = node.wait_status("a listen address", |status| status["listen_addrs"][0].is_string()).await;
+        node.addr = status["listen_addrs"][0].as_str().unwrap().parse().unwrap();
+        node.wait_status("connections to its bootstrap peers", |status| {
+            status["connected_peers"].as_u64().unwrap() >= peers.len() as u64
+        })
+        .await;
+        // until a peer identified the node it doesn't know where it listens, and its DHT lookups
+        // pass the node over
+        let id = node.peer_id.to_string();
+        for peer in peers {
+            wait_for("bootstrap peers to identify the node", || async {
+                let known = peer.call("peers", Value::Null).await.expect("peers succeeds");
+                known.as_array().unwrap().iter().any(|info| info["peer_id"] == id && info["agent"].is_string())
+            })
+            .await;
+        }
+        node
+    }
+
+    /// A file of `size` bytes in the test directory, the same content for the same seed.
+    pub fn file(&self, name: &str, size: usize, seed: u64) -> PathBuf {
+        let mut content = vec![0; size];
+        StdRng::seed_from_u64(seed).fill_bytes(&mut content);
+        let path = self.dir.join(name);
+        fs::write(&path, content).expect("write the test file");
+        path
+    }
+
+    /// Stops every node and removes the directory, once no database in it is open any more.
+    pub async fn stop(self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        for task in self.tasks {
+            let _ = time::timeout(TIMEOUT, task).await;
+        }
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// A node started by a test, driven through the control API.
+pub struct Node {
+    pub peer_id: PeerId,
+    pub addr: Multiaddr, // listen address with /p2p/<peer id>, what others bootstrap to
+    pub retrieved: PathBuf, // where gets are written
+    commands: mpsc::UnboundedSender<Command>,
+    task: AbortHandle, // the task itself is the network's to wait for
+}
+
+impl Node {
+    /// Runs a control API method as `rust_dfs ctl` would, the error is the JSON-RPC message.
+    pub async fn call(&self, method: &str, params: Value) -> Result<Value, String> {
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        let response = time::timeout(TIMEOUT, control::handle(&request.to_string(), &self.commands, &PutAccess::Anywhere))
+            .await
+            .unwrap_or_else(|_| panic!("{method} timed out"))
+            .expect("requests with an id are answered");
+        let response: Value = serde_json::from_str(&response).expect("response is JSON");
+        match response.get("error") {
+            Some(error) => Err(error["message"].as_str().unwrap_or_default().to_string()),
+            None => Ok(response["result"].clone()),
+        }
+    }
+
+    pub async fn put(&self, path: &Path) -> String {
+        let stored = self.call("put", json!({ "path": path })).await.expect("put succeeds");
+        stored["key"].as_str().unwrap().to_string()
+    }
+
+    // the retrieved file's content, once downloaded
+    pub async fn get(&self, key: &str) -> Result<Vec<u8>, String> {
+        let got = self.call("get", json!({ "key": key })).await?;
+        let path = got["path"].as_str().ok_or(format!("no path in {got}"))?;
+        fs::read(path).map_err(|e| e.to_string())
+    }
+
+    pub async fn has(&self, key: &str) -> bool {
+        let files = self.call("list", Value::Null).await.expect("list succeeds");
+        files.as_array().unwrap().iter().any(|file| file["key"] == key)
+    }
+
+    // live providers of a file we hold, as the last replication check counted them
+    pub async fn providers(&self, key: &str) -> Option<u64> {
+        let status = self.call("status", Value::Null).await.expect("status succeeds");
+        let files = status["files"].as_array().unwrap();
+        files.iter().find(|file| file["key"] == key)?["providers"].as_u64()
+    }
+
+    /// Polls the status until `ready` holds, panics after TIMEOUT.
+    pub async fn wait_status(&self, what: &str, ready: impl Fn(&Value) -> bool) -> Value {
+        let waited = time::timeout(TIMEOUT, async {
+            loop {
+                let status = self.call("status", Value::Null).await.expect("status succeeds");
+                if ready(&status) {
+                    return status;
+                }
+                time::sleep(Duration::from_millis(50)).await;
+            }
+        });
+        waited.await.unwrap_or_else(|_| panic!("node {} waited too long for {what}", self.peer_id))
+    }
+
+    /// Stops the node as if it crashed, its connections close without a goodbye.
+    pub fn kill(self) {
+        self.task.abort();
+    }
+}
+
+/// Polls `check` until it holds, panics after TIMEOUT.
+pub async fn wait_for<F: Future<Output = bool>>(what: &str, check: impl Fn() -> F) {
+    let waited = time::timeout(TIMEOUT, async {
+        while !check().await {
+            time::sleep(Duration::from_millis(50)).await;
+        }
+    });
+    waited.await.unwrap_or_else(|_| panic!("waited too long for {what}"));
+}
+
+const FAKE_AGENT: &str = "fake-provider";
+
+/// How a fake provider misbehaves.
+#[derive(Clone, Copy)]
+pub enum Fault {
+    Corrupt,          // every chunk comes back with its bytes flipped
+    DieAfter(usize),  // serves this many chunks, then drops all its connections
+    Forge,            // serves the manifest and chunks of other content, consistent but not the key's
+}
+
+/// A fake provider's file and the task serving it, which ends with the chunks it sent.
+pub struct Fake {
+    pub key: String,
+    pub peer_id: PeerId,
+    pub task: JoinHandle<usize>,
+}
+
+#[derive(NetworkBehaviour)]
+struct FakeBehaviour {
+    kademlia: kad::Behaviour<MemoryStore>,
+    transfer: TransferBehaviour,
+    identify: identify::Behaviour, // tells the node where we listen, so its lookups ask us
+}
+
+/// A peer that isn't a rust_dfs node, it speaks the file protocol to announce one file to
+/// `peer` and serve it with a fault.
+pub async fn fake_provider(path: &Path, fault: Fault, peer: &Node) -> Fake {
+    let mut chunks = HashMap::new();
+    let mut content = fs::read(path).expect("read the test file");
+    let name = path.file_name().unwrap().to_string_lossy();
+    let (_, key) = chunk::split(&name, content.as_slice(), |_, _| Ok(())).expect("split the test file");
+    if let Fault::Forge = fault {
+        content.reverse();
+    }
+    let (manifest, _) = chunk::split(&name, content.as_slice(), |hash, data| {
+        chunks.insert(hash.to_string(), data.to_vec());
+        Ok(())
+    })
+    .expect("split the test file");
+
+    let transports = Transports::parse(&["memory"]).unwrap();
+    let mut swarm = libp2p::SwarmBuilder::with_new_identity()
+        .with_tokio()
+        .with_other_transport(|key| transport::build(key, transports))
+        .unwrap()
+        .with_behaviour(|key| {
+            let peer_id = key.public().to_peer_id();
+            Ok(FakeBehaviour {
+                kademlia: kad::Behaviour::with_config(peer_id, MemoryStore::new(peer_id), kad::Config::new(kad::PROTOCOL_NAME)),
+                transfer: request_response::Behaviour::new([(FileProtocol, ProtocolSupport::Full)], request_response::Config::default()),
+                identify: identify::Behaviour::new(
+                    identify::Config::new(IDENTIFY_PROTOCOL.to_string(), key.public()).with_agent_version(FAKE_AGENT.to_string()),
+                ),
+            })
+        })
+        .unwrap()
+        .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
+        .build();
+    let peer_id = *swarm.local_peer_id();
+    swarm.behaviour_mut().kademlia.set_mode(Some(Mode::Server));
+    swarm.behaviour_mut().kademlia.start_providing(kad::RecordKey::new(&key)).unwrap();
+    swarm.listen_on("/memory/0".parse().unwrap()).unwrap();
+    // identify only reports addresses we listen on already
+    let listening = time::timeout(TIMEOUT, async {
+        while !matches!(swarm.select_next_some().await, SwarmEvent::NewListenAddr { .. }) {}
+    });
+    listening.await.expect("listen in time");
+    swarm.behaviour_mut().kademlia.add_address(&peer.peer_id, peer.addr.clone());
+    swarm.dial(peer.addr.clone()).expect("dial the peer");
+
+    let task = tokio::spawn(async move {
+        let mut served = HashSet::new(); // chunk responses handed to the swarm
+        let mut sent = 0;
+        loop {
+            let (request_id, request, channel) = match swarm.select_next_some().await {
+                SwarmEvent::Behaviour(FakeBehaviourEvent::Transfer(request_response::Event::Message {
+                    message: request_response::Message::Request { request_id, request, channel },
+                    ..
+                })) => (request_id, request, channel),
+                SwarmEvent::Behaviour(FakeBehaviourEvent::Transfer(request_response::Event::ResponseSent { request_id, .. }))
+                    if served.contains(&request_id) =>
+                {
+                    sent += 1;
+                    if matches!(fault, Fault::DieAfter(limit) if sent == limit) {
+                        break;
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            let response = match request {
+                FileRequest::Manifest { .. } => FileResponse::Manifest(manifest.clone()),
+                FileRequest::Chunk { hash } => match (chunks.get(&hash), fault) {
+                    (None, _) => FileResponse::NotFound,
+                    // past the limit requests go unanswered until we are gone
+                    (Some(_), Fault::DieAfter(limit)) if served.len() == limit => continue,
+                    (Some(data), Fault::Corrupt) => FileResponse::Chunk(data.iter().map(|byte| !byte).collect()),
+                    (Some(data), Fault::DieAfter(_) | Fault::Forge) => FileResponse::Chunk(data.clone()),
+                },
+                FileRequest::Replicate { .. } => FileResponse::NotFound,
+            };
+            if matches!(response, FileResponse::Chunk(_)) {
+                served.insert(request_id);
+            }
+            let _ = swarm.behaviour_mut().transfer.send_response(channel, response);
+        }
+        // a response counts as sent once written to the stream, give the connection a moment
+        // to deliver it before the swarm and all its connections are dropped
+        let _ = time::timeout(Duration::from_millis(200), async {
+            loop {
+                swarm.select_next_some().await;
+            }
+        })
+        .await;
+        sent
+    });
+
+    // the provider record we put may still be on its way, once the node knows where we listen
+    // its lookups ask us and we answer with ourselves
+    let fake = peer_id.to_string();
+    wait_for("the node to identify the fake provider", || async {
+        let peers = peer.call("peers", Value::Null).await.expect("peers succeeds");
+        peers.as_array().unwrap().iter().any(|info| info["peer_id"] == fake && info["agent"] == FAKE_AGENT)
+    })
+    .await;
+    Fake { key, peer_id, task }
+}
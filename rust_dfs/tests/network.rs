@@ -0,0 +1,201 @@
+mod harness;
+
+use std::fs;
+use harness::{fake_provider, wait_for, Fault, Network, TICK, TIMEOUT};
+use serde_json::{json, Value};
+use tokio::time;
+
+// a few chunks of 64 KiB to 1 MiB, more than a download keeps in flight
+const FILE_SIZE: usize = 4 * 1024 * 1024;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn get_finds_a_file_through_the_dht() {
+    let mut net = Network::memory();
+    let a = net.start(1, &[]).await;
+    let b = net.start(1, &[&a]).await;
+    // c only knows b, the provider record of a is found through it
+    let c = net.start(1, &[&b]).await;
+    let path = net.file("file.bin", FILE_SIZE, 1);
+
+    let key = a.put(&path).await;
+    assert_eq!(c.get(&key).await.unwrap(), fs::read(&path).unwrap());
+    assert!(c.has(&key).await);
+    // b only routed the lookup
+    assert!(!b.has(&key).await);
+    net.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn get_over_tcp_loopback() {
+    let mut net = Network::tcp();
+    let a = net.start(1, &[]).await;
+    let b = net.start(1, &[&a]).await;
+    let path = net.file("file.bin", FILE_SIZE, 2);
+
+    let key = a.put(&path).await;
+    assert_eq!(b.get(&key).await.unwrap(), fs::read(&path).unwrap());
+    net.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn get_of_an_unknown_key_fails() {
+    let mut net = Network::memory();
+    let a = net.start(1, &[]).await;
+    let b = net.start(1, &[&a]).await;
+
+    let error = b.get("no-such-key").await.unwrap_err();
+    assert!(error.contains("no provider"), "{error}");
+    net.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn files_are_replicated_up_to_the_factor() {
+    let mut net = Network::memory();
+    let a = net.start(3, &[]).await;
+    let b = net.start(3, &[&a]).await;
+    let c = net.start(3, &[&a]).await;
+    let path = net.file("file.bin", FILE_SIZE, 3);
+
+    let key = a.put(&path).await;
+    wait_for("three providers", || async { a.providers(&key).await == Some(3) }).await;
+    assert!(b.has(&key).await);
+    assert!(c.has(&key).await);
+    // replicas stay in sled, nobody asked for them on disk
+    assert!(!b.retrieved.exists());
+    assert!(!c.retrieved.exists());
+    net.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn replicate_is_refused_over_quota() {
+    let mut net = Network::memory();
+    let a = net.start(2, &[]).await;
+    let b = net.start_with(2, &[&a], &["--quota", "1"]).await;
+    // b's own file is pinned and already uses up its quota
+    b.put(&net.file("own.bin", FILE_SIZE, 5)).await;
+
+    let key = a.put(&net.file("file.bin", FILE_SIZE, 6)).await;
+    wait_for("a replication check", || async { a.providers(&key).await.is_some() }).await;
+    time::sleep(4 * TICK).await;
+    assert_eq!(a.providers(&key).await, Some(1));
+    assert!(!b.has(&key).await);
+    net.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shared_chunks_count_once_against_the_quota() {
+    let mut net = Network::memory();
+    let a = net.start(1, &[]).await;
+    let path = net.file("file.bin", FILE_SIZE, 7);
+    a.put(&path).await;
+    a.call("put", json!({ "path": path, "key": "copy" })).await.unwrap();
+    let status = a.call("status", Value::Null).await.unwrap();
+    assert_eq!(status["used_bytes"], FILE_SIZE as u64);
+    net.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn files_that_look_like_directories_stay_files() {
+    let mut net = Network::memory();
+    let a = net.start(1, &[]).await;
+    let path = net.file("fake_dir.bin", 0, 8);
+    fs::write(&path, b"DFSD\x01{\"name\":\"fake\",\"entries\":[]}").unwrap();
+    let key = a.put(&path).await;
+    let files = a.call("list", Value::Null).await.unwrap();
+    let file = files.as_array().unwrap().iter().find(|file| file["key"] == key.as_str()).unwrap();
+    assert_eq!(file["directory"], false);
+    assert_eq!(a.get(&key).await.unwrap(), fs::read(&path).unwrap());
+    net.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn replicas_lost_to_churn_are_made_again() {
+    let mut net = Network::memory();
+    let a = net.start(3, &[]).await;
+    let b = net.start(3, &[&a]).await;
+    let c = net.start(3, &[&a]).await;
+    let path = net.file("file.bin", FILE_SIZE, 4);
+    let key = a.put(&path).await;
+    wait_for("three providers", || async { a.providers(&key).await == Some(3) }).await;
+
+    // b leaves, a takes d on as a replica in its place
+    b.kill();
+    let d = net.start(3, &[&a]).await;
+    wait_for("d to hold a copy", || async { d.has(&key).await }).await;
+    wait_for("three providers again", || async { a.providers(&key).await == Some(3) }).await;
+
+    // the node that put the file leaves too, the copies are enough for a newcomer
+    a.kill();
+    let e = net.start(3, &[&c]).await;
+    assert_eq!(e.get(&key).await.unwrap(), fs::read(&path).unwrap());
+    net.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn download_resumes_after_its_provider_dies() {
+    let mut net = Network::memory();
+    let c = net.start(1, &[]).await;
+    let path = net.file("file.bin", FILE_SIZE, 5);
+    let fake = fake_provider(&path, Fault::DieAfter(3), &c).await;
+
+    let error = c.get(&fake.key).await.unwrap_err();
+    assert!(error.contains("all providers failed"), "{error}");
+    let sent = time::timeout(TIMEOUT, fake.task).await.expect("the fake provider stops");
+    assert_eq!(sent.unwrap(), 3);
+    // what arrived before the provider died is kept
+    let status = c.call("status", Value::Null).await.unwrap();
+    let download = &status["downloads"][0];
+    assert_eq!(download["state"], "paused");
+    let received = download["received"].as_u64().unwrap();
+    assert!(received > 0 && received < download["chunks"].as_u64().unwrap(), "{download}");
+
+    // another provider shows up, the rest comes from it
+    let a = net.start(1, &[&c]).await;
+    assert_eq!(a.put(&path).await, fake.key);
+    assert_eq!(c.get(&fake.key).await.unwrap(), fs::read(&path).unwrap());
+    net.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn corrupted_chunks_are_rejected() {
+    let mut net = Network::memory();
+    let c = net.start(1, &[]).await;
+    let path = net.file("file.bin", FILE_SIZE, 6);
+    let fake = fake_provider(&path, Fault::Corrupt, &c).await;
+
+    let error = c.get(&fake.key).await.unwrap_err();
+    assert!(error.contains("all providers failed (0/"), "{error}");
+    assert!(!c.has(&fake.key).await);
+    // every chunk it sent was wrong, its score fell low enough to ban it
+    let peer = fake.peer_id.to_string();
+    wait_for("the fake provider to be disconnected", || async {
+        let peers = c.call("peers", Value::Null).await.unwrap();
+        peers.as_array().unwrap().iter().any(|info| info["peer_id"] == peer && info["state"] == "disconnected")
+    })
+    .await;
+    fake.task.abort();
+
+    let a = net.start(1, &[&c]).await;
+    assert_eq!(a.put(&path).await, fake.key);
+    assert_eq!(c.get(&fake.key).await.unwrap(), fs::read(&path).unwrap());
+    net.stop().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn forged_manifests_are_rejected() {
+    let mut net = Network::memory();
+    let c = net.start(1, &[]).await;
+    let path = net.file("file.bin", FILE_SIZE, 7);
+    let fake = fake_provider(&path, Fault::Forge, &c).await;
+
+    // every chunk matches the manifest, the content doesn't match the key
+    let error = c.get(&fake.key).await.unwrap_err();
+    assert!(error.contains("no provider sent the manifest"), "{error}");
+    assert!(!c.has(&fake.key).await);
+
+    let a = net.start(1, &[&c]).await;
+    assert_eq!(a.put(&path).await, fake.key);
+    assert_eq!(c.get(&fake.key).await.unwrap(), fs::read(&path).unwrap());
+    fake.task.abort();
+    net.stop().await;
+}